@@ -0,0 +1,179 @@
+use std::{collections::HashSet, path::PathBuf, str::FromStr};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::{AccountRef, Verifier},
+        anyhow::{bail, Error, Result},
+        value::{chrono::DateTime, hash::Hash},
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::sync::Mutex,
+};
+use ipsis_api_persistent_common::journal::{IpsisJournal, JournalTokens};
+use ipsis_common::SignedCapability;
+
+/// A list of the revoked grants, which is persisted as a journal file.
+pub struct IpsisRevocations {
+    state: Mutex<RevocationsState>,
+}
+
+struct RevocationsState {
+    entries: HashSet<(AccountRef, Hash)>,
+    journal: IpsisJournal,
+}
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisRevocations {
+    type GenesisArgs = PathBuf;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let path = infer("ipsis_client_revocations_path").or_else(|e| {
+            let mut path = ::dirs::home_dir().ok_or(e)?;
+            path.push(".ipsis");
+            path.push("revocations");
+            Result::<_, Error>::Ok(path)
+        })?;
+        Self::genesis(path).await
+    }
+
+    async fn genesis(
+        path: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::open(path).await
+    }
+}
+
+impl IpsisRevocations {
+    async fn open(path: PathBuf) -> Result<Self> {
+        let (mut journal, records) = IpsisJournal::open(path).await?;
+
+        // replay the journal
+        let entries: HashSet<_> = records
+            .into_iter()
+            .map(
+                |Record {
+                     grantor,
+                     capability,
+                 }| (grantor, capability),
+            )
+            .collect();
+
+        // compact the journal
+        journal
+            .compact(entries.iter().map(|&(grantor, capability)| Record {
+                grantor,
+                capability,
+            }))
+            .await?;
+
+        Ok(Self {
+            state: Mutex::new(RevocationsState { entries, journal }),
+        })
+    }
+
+    pub async fn contains(&self, grantor: &AccountRef, capability: &Hash) -> bool {
+        let state = self.state.lock().await;
+        state.entries.contains(&(*grantor, *capability))
+    }
+
+    /// Revokes a grant which has been signed by the grantor.
+    pub async fn revoke(&self, grantor: &AccountRef, capability: &Hash) -> Result<()> {
+        let mut state = self.state.lock().await;
+        if state.entries.contains(&(*grantor, *capability)) {
+            return Ok(());
+        }
+
+        // write to the journal
+        let record = Record {
+            grantor: *grantor,
+            capability: *capability,
+        };
+        state.journal.append(&record).await?;
+        state.journal.sync().await?;
+
+        // apply to the entries
+        state.entries.insert((*grantor, *capability));
+        Ok(())
+    }
+
+    /// Verifies the chain of grants, and returns the owner of the path.
+    ///
+    /// If the chain is empty, the guarantee itself is the owner.
+    pub async fn verify_chain(
+        &self,
+        guarantee: &AccountRef,
+        path: &Path,
+        capabilities: &[SignedCapability],
+    ) -> Result<AccountRef> {
+        let owner = match capabilities.first() {
+            Some(capability) => capability.metadata.guarantee.account,
+            None => return Ok(*guarantee),
+        };
+
+        let now = DateTime::now();
+        let mut grantor = owner;
+        for capability in capabilities {
+            // verify the signature
+            let grantee = capability.data.grantee;
+            let _ = capability.verify(Some(&grantee))?;
+
+            // verify the chain
+            if capability.metadata.guarantee.account != grantor {
+                bail!("broken chain of grants");
+            }
+            if !capability.data.paths.contains(path) {
+                bail!("the path is not granted: {}", path.value.to_string());
+            }
+            if matches!(&capability.data.expiration_date, Some(date) if date <= &now) {
+                bail!("the grant is expired");
+            }
+            if self.contains(&grantor, &capability.data.to_hash()?).await {
+                bail!("the grant is revoked");
+            }
+
+            grantor = grantee;
+        }
+
+        // the last grantee should be the requester
+        if &grantor == guarantee {
+            Ok(owner)
+        } else {
+            bail!(
+                "the grant is not for the account: {}",
+                guarantee.to_string()
+            )
+        }
+    }
+}
+
+struct Record {
+    grantor: AccountRef,
+    capability: Hash,
+}
+
+impl ::core::fmt::Display for Record {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        writeln!(
+            f,
+            "{} {}",
+            self.grantor.to_string(),
+            self.capability.to_string(),
+        )
+    }
+}
+
+impl FromStr for Record {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut tokens = JournalTokens::new("revocation", line);
+
+        Ok(Self {
+            grantor: tokens.next_token()?.parse()?,
+            capability: tokens.next_token()?.parse()?,
+        })
+    }
+}
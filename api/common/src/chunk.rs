@@ -0,0 +1,259 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::sync::Mutex,
+};
+use ipsis_api_persistent_common::journal::{IpsisJournal, JournalKey, JournalTokens};
+use ipsis_common::Manifest;
+
+/// An index of the stored manifests, and the number of the manifests which refer each chunk.
+///
+/// Every change is appended to a journal file, which is replayed and compacted on startup
+/// and periodically by the garbage collector.
+pub struct IpsisChunks {
+    state: Mutex<ChunksState>,
+}
+
+struct ChunksState {
+    manifests: HashMap<ChunksKey, Manifest>,
+    chunks: HashMap<ChunksKey, usize>,
+    journal: IpsisJournal,
+}
+
+type ChunksKey = JournalKey;
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisChunks {
+    type GenesisArgs = PathBuf;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let path = infer("ipsis_client_chunks_path").or_else(|e| {
+            let mut path = ::dirs::home_dir().ok_or(e)?;
+            path.push(".ipsis");
+            path.push("chunks");
+            Result::<_, Error>::Ok(path)
+        })?;
+        Self::genesis(path).await
+    }
+
+    async fn genesis(
+        path: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::open(path).await
+    }
+}
+
+impl IpsisChunks {
+    async fn open(path: PathBuf) -> Result<Self> {
+        let (journal, records) = IpsisJournal::open(path).await?;
+
+        // replay the journal
+        let mut manifests = HashMap::default();
+        for record in records {
+            match record {
+                Record::Add { key, manifest } => {
+                    manifests.insert(key, manifest);
+                }
+                Record::Remove { key } => {
+                    manifests.remove(&key);
+                }
+            }
+        }
+
+        // count the references of the chunks
+        let mut chunks = HashMap::default();
+        for (key, manifest) in &manifests {
+            for chunk in &manifest.chunks {
+                *chunks
+                    .entry(ChunksKey::new(key.namespace.as_ref(), chunk))
+                    .or_default() += 1;
+            }
+        }
+
+        // compact the journal
+        let mut state = ChunksState {
+            manifests,
+            chunks,
+            journal,
+        };
+        state.compact().await?;
+
+        Ok(Self {
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Rewrites the journal with the current manifests, dropping the removed ones.
+    pub async fn compact(&self) -> Result<()> {
+        self.state.lock().await.compact().await
+    }
+
+    /// Returns the manifest of the path, if the path is composed of chunks.
+    pub async fn get_manifest(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+    ) -> Option<Manifest> {
+        let key = ChunksKey::new(namespace, path);
+
+        let state = self.state.lock().await;
+        state
+            .manifests
+            .get(&key)
+            // the manifest itself can be also stored as a plain object
+            .filter(|manifest| manifest.content_len() == path.len)
+            .cloned()
+    }
+
+    /// Returns whether the path is a chunk of any stored manifest.
+    pub async fn is_referred(&self, namespace: Option<&AccountRef>, path: &Path) -> bool {
+        let key = ChunksKey::new(namespace, path);
+
+        let state = self.state.lock().await;
+        state.chunks.contains_key(&key)
+    }
+
+    /// Registers the manifest, so that its chunks are kept until the manifest is removed.
+    pub async fn add_manifest(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        let key = ChunksKey::new(namespace, path);
+
+        let mut state = self.state.lock().await;
+        if state.manifests.contains_key(&key) {
+            return Ok(());
+        }
+
+        // write to the journal
+        let record = Record::Add {
+            key,
+            manifest: manifest.clone(),
+        };
+        state.write(&record).await?;
+
+        // apply to the entries
+        for chunk in &manifest.chunks {
+            *state
+                .chunks
+                .entry(ChunksKey::new(namespace, chunk))
+                .or_default() += 1;
+        }
+        state.manifests.insert(key, manifest.clone());
+        Ok(())
+    }
+
+    /// Unregisters the manifest, and returns the chunks which are no longer referred by any manifest.
+    ///
+    /// Returns `None` if the path is not a manifest.
+    pub async fn remove_manifest(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+    ) -> Result<Option<Vec<Path>>> {
+        let key = ChunksKey::new(namespace, path);
+
+        let mut state = self.state.lock().await;
+        if !state.manifests.contains_key(&key) {
+            return Ok(None);
+        }
+
+        // write to the journal
+        let record = Record::Remove { key };
+        state.write(&record).await?;
+
+        // apply to the entries
+        let manifest = state.manifests.remove(&key).unwrap();
+        let mut released = vec![];
+        for chunk in manifest.chunks {
+            let key = ChunksKey::new(namespace, &chunk);
+            if let Some(count) = state.chunks.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    state.chunks.remove(&key);
+                    released.push(chunk);
+                }
+            }
+        }
+        Ok(Some(released))
+    }
+}
+
+impl ChunksState {
+    async fn compact(&mut self) -> Result<()> {
+        let records = self.manifests.iter().map(|(key, manifest)| Record::Add {
+            key: *key,
+            manifest: manifest.clone(),
+        });
+        self.journal.compact(records).await
+    }
+
+    /// Appends the record durably, as the chunks may be deleted by trusting it.
+    async fn write(&mut self, record: &Record) -> Result<()> {
+        self.journal.append(record).await?;
+        self.journal.sync().await
+    }
+}
+
+enum Record {
+    Add { key: ChunksKey, manifest: Manifest },
+    Remove { key: ChunksKey },
+}
+
+impl ::core::fmt::Display for Record {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match self {
+            Self::Add { key, manifest } => {
+                write!(f, "+ {key}")?;
+                for chunk in &manifest.chunks {
+                    write!(f, " {}:{}", chunk.value.to_string(), chunk.len)?;
+                }
+                writeln!(f)
+            }
+            Self::Remove { key } => writeln!(f, "- {key}"),
+        }
+    }
+}
+
+impl FromStr for Record {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut tokens = JournalTokens::new("chunks", line);
+
+        let op = tokens.next_token()?;
+        let key = tokens.next_key()?;
+
+        match op {
+            "+" => Ok(Self::Add {
+                key,
+                manifest: Manifest {
+                    chunks: tokens
+                        .into_rest()
+                        .map(|chunk| {
+                            let (value, len) = chunk
+                                .split_once(':')
+                                .ok_or_else(|| anyhow!("malformed chunk: {chunk:?}"))?;
+                            Ok(Path {
+                                value: value.parse()?,
+                                len: len.parse()?,
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                },
+            }),
+            "-" => Ok(Self::Remove { key }),
+            op => bail!("unknown chunks operation: {op:?}"),
+        }
+    }
+}
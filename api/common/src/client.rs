@@ -1,31 +1,66 @@
-use std::sync::Arc;
+use std::{io::Cursor, sync::Arc, time::Duration};
 
 use ipiis_api::common::Ipiis;
 use ipis::{
     async_trait::async_trait,
     core::{
+        account::AccountRef,
         anyhow::{bail, Error, Result},
-        value::hash::Hasher,
+        chrono::{self, Utc},
+        signed::IsSigned,
+        value::{
+            chrono::DateTime,
+            hash::{Hash, Hasher},
+        },
     },
     env::Infer,
+    futures::future::try_join_all,
+    log::warn,
     path::Path,
     tokio::{
         self,
+        fs::File,
         io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+        sync::OnceCell,
     },
 };
 use ipsis_api_persistent_common::IpsisPersistentStorage;
-use ipsis_common::Ipsis;
+use ipsis_common::{
+    chunk::ManifestHasher, hash_file, Capability, Ipsis, Lease, ListPage, ListQuery, Manifest,
+    PathRange, Revocation, ScrubEntry, ScrubQuery, ScrubReport, SignedCapability, Stat,
+    LIST_LIMIT_MAX,
+};
 
-use crate::config::IpsisClientConfig;
+use crate::{
+    capability::IpsisRevocations,
+    chunk::IpsisChunks,
+    config::IpsisClientConfig,
+    ledger::{ExpiredReference, IpsisLedger},
+    lock::IpsisPathLocks,
+    policy::{IpsisOperation, IpsisPolicy, IpsisPolicyRules, PermissionDenied},
+    scrub::{IpsisScrubber, ScrubCheckpoint, ScrubRateLimiter},
+    upload::IpsisUploads,
+    verify::{HashingReader, IntegrityError, VerifyingReader},
+};
 
 pub type IpsisClient<PersistentStorage> =
     IpsisClientInner<::ipiis_api::client::IpiisClient, PersistentStorage>;
 
+/// A client which serves the requests with the persistent storage.
+///
+/// The journals of the bookkeeping are opened on the first use,
+/// so that the clients which only forward the requests do not lock them.
 pub struct IpsisClientInner<IpiisClient, PersistentStorage> {
     pub ipiis: IpiisClient,
+    chunks: OnceCell<IpsisChunks>,
     config: IpsisClientConfig,
+    ledger: OnceCell<IpsisLedger>,
+    locks: IpsisPathLocks,
     persistent_storage: Arc<PersistentStorage>,
+    policy: Box<dyn IpsisPolicy + Send + Sync>,
+    revocations: OnceCell<IpsisRevocations>,
+    scrubber: OnceCell<IpsisScrubber>,
+    uploads: OnceCell<IpsisUploads>,
 }
 
 impl<IpiisClient, PersistentStorage> AsRef<::ipiis_api::client::IpiisClient>
@@ -63,8 +98,15 @@ where
     async fn try_infer() -> Result<Self> {
         Ok(Self {
             ipiis: IpiisClient::try_infer().await?,
+            chunks: Default::default(),
             config: Default::default(),
+            ledger: Default::default(),
+            locks: Default::default(),
             persistent_storage: PersistentStorage::try_infer().await?.into(),
+            policy: Box::new(IpsisPolicyRules::try_infer().await?),
+            revocations: Default::default(),
+            scrubber: Default::default(),
+            uploads: Default::default(),
         })
     }
 
@@ -73,8 +115,15 @@ where
     ) -> Result<<Self as Infer<'a>>::GenesisResult> {
         Ok(Self {
             ipiis: IpiisClient::genesis(args).await?,
+            chunks: Default::default(),
             config: Default::default(),
+            ledger: Default::default(),
+            locks: Default::default(),
             persistent_storage: PersistentStorage::try_infer().await?.into(),
+            policy: Box::new(IpsisPolicyRules::try_infer().await?),
+            revocations: Default::default(),
+            scrubber: Default::default(),
+            uploads: Default::default(),
         })
     }
 }
@@ -85,29 +134,303 @@ where
     IpiisClient: Ipiis + Send + Sync,
     PersistentStorage: IpsisPersistentStorage + Send + Sync + 'static,
 {
-    type Reader = tokio::io::DuplexStream;
+    type Reader = VerifyingReader<tokio::io::DuplexStream>;
 
     async fn protocol(&self) -> Result<String> {
-        Ok(<PersistentStorage as IpsisPersistentStorage>::PROTOCOL.into())
+        Ok(self.persistent_storage.protocol().into())
     }
 
     async fn get_raw(&self, path: &Path) -> Result<<Self as Ipsis>::Reader> {
+        self.get_raw_for(self.ipiis.account_ref(), path).await
+    }
+
+    async fn get_raw_shared(
+        &self,
+        path: &Path,
+        capabilities: &[SignedCapability],
+    ) -> Result<<Self as Ipsis>::Reader> {
+        self.get_raw_shared_for(self.ipiis.account_ref(), path, capabilities)
+            .await
+    }
+
+    async fn get_raw_range(
+        &self,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<<Self as Ipsis>::Reader> {
+        self.get_raw_range_for(self.ipiis.account_ref(), path, offset, len)
+            .await
+    }
+
+    async fn put_raw_with_lease<R>(
+        &self,
+        path: &Path,
+        data: R,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let lease = Lease {
+            path: *path,
+            expiration_date,
+        };
+
+        self.put_raw_for(self.ipiis.account_ref(), &lease, data)
+            .await
+    }
+
+    async fn import_raw(
+        &self,
+        local_path: &::std::path::Path,
+        expiration_date: Option<DateTime>,
+    ) -> Result<Path> {
+        self.import_raw_for(self.ipiis.account_ref(), local_path, expiration_date)
+            .await
+    }
+
+    async fn begin_put(&self, path: &Path) -> Result<u64> {
+        self.begin_put_for(self.ipiis.account_ref(), path).await
+    }
+
+    async fn put_part<R>(&self, path: &Path, offset: u64, len: u64, data: R) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        let range = PathRange {
+            path: *path,
+            offset,
+            len,
+        };
+
+        self.put_part_for(self.ipiis.account_ref(), &range, data)
+            .await
+    }
+
+    async fn commit_put(&self, path: &Path, expiration_date: Option<DateTime>) -> Result<()> {
+        let lease = Lease {
+            path: *path,
+            expiration_date,
+        };
+
+        self.commit_put_for(self.ipiis.account_ref(), &lease).await
+    }
+
+    async fn put_manifest(
+        &self,
+        manifest: &Manifest,
+        expiration_date: Option<DateTime>,
+    ) -> Result<Path> {
+        let lease = Lease {
+            path: manifest.to_path()?,
+            expiration_date,
+        };
+
+        self.put_manifest_for(self.ipiis.account_ref(), &lease, manifest)
+            .await?;
+        Ok(lease.path)
+    }
+
+    async fn renew(&self, path: &Path, expiration_date: Option<DateTime>) -> Result<()> {
+        let lease = Lease {
+            path: *path,
+            expiration_date,
+        };
+
+        self.renew_for(self.ipiis.account_ref(), &lease).await
+    }
+
+    async fn contains(&self, path: &Path) -> Result<bool> {
+        self.contains_for(self.ipiis.account_ref(), path).await
+    }
+
+    async fn stat(&self, path: &Path) -> Result<Stat> {
+        self.stat_for(self.ipiis.account_ref(), path).await
+    }
+
+    async fn delete(&self, path: &Path) -> Result<()> {
+        self.delete_for(self.ipiis.account_ref(), path).await
+    }
+
+    async fn list(&self, query: &ListQuery) -> Result<ListPage> {
+        self.list_for(self.ipiis.account_ref(), query).await
+    }
+
+    async fn grant(
+        &self,
+        grantee: &AccountRef,
+        paths: Vec<Path>,
+        expiration_date: Option<DateTime>,
+    ) -> Result<SignedCapability> {
+        self.ipiis.grant(grantee, paths, expiration_date).await
+    }
+
+    async fn revoke(&self, capability: &Capability) -> Result<()> {
+        let revocation = Revocation {
+            capability: capability.to_hash()?,
+        };
+
+        self.revoke_for(self.ipiis.account_ref(), &revocation).await
+    }
+
+    async fn scrub(&self, query: &ScrubQuery) -> Result<ScrubReport> {
+        self.scrub_storage(query).await
+    }
+}
+
+impl<IpiisClient, PersistentStorage> IpsisClientInner<IpiisClient, PersistentStorage>
+where
+    IpiisClient: Ipiis + Send + Sync,
+    PersistentStorage: IpsisPersistentStorage + Send + Sync + 'static,
+{
+    pub fn config(&self) -> &IpsisClientConfig {
+        &self.config
+    }
+
+    pub fn set_policy<Policy>(&mut self, policy: Policy)
+    where
+        Policy: IpsisPolicy + Send + Sync + 'static,
+    {
+        self.policy = Box::new(policy);
+    }
+
+    /// Checks whether the guarantee is allowed to do the operation.
+    pub fn authorize(
+        &self,
+        guarantee: &AccountRef,
+        operation: IpsisOperation,
+    ) -> Result<(), PermissionDenied> {
+        self.policy.authorize(guarantee, operation)
+    }
+
+    /// Resolves the account whose namespace stores the requests of the given guarantee.
+    pub fn namespace(&self, guarantee: &AccountRef) -> AccountRef {
+        if self.config.enable_shared_namespace {
+            *self.ipiis.account_ref()
+        } else {
+            *guarantee
+        }
+    }
+
+    /// Opens the journals of the bookkeeping ahead of the first request.
+    pub async fn open_journals(&self) -> Result<()> {
+        self.chunks().await?;
+        self.ledger().await?;
+        self.revocations().await?;
+        self.scrubber().await?;
+        self.uploads().await?;
+        Ok(())
+    }
+
+    async fn chunks(&self) -> Result<&IpsisChunks> {
+        self.chunks.get_or_try_init(IpsisChunks::try_infer).await
+    }
+
+    async fn ledger(&self) -> Result<&IpsisLedger> {
+        self.ledger.get_or_try_init(IpsisLedger::try_infer).await
+    }
+
+    async fn revocations(&self) -> Result<&IpsisRevocations> {
+        self.revocations
+            .get_or_try_init(IpsisRevocations::try_infer)
+            .await
+    }
+
+    async fn scrubber(&self) -> Result<&IpsisScrubber> {
+        self.scrubber
+            .get_or_try_init(IpsisScrubber::try_infer)
+            .await
+    }
+
+    async fn uploads(&self) -> Result<&IpsisUploads> {
+        self.uploads.get_or_try_init(IpsisUploads::try_infer).await
+    }
+
+    /// Returns whether any account or manifest refers the path.
+    async fn is_referred(
+        &self,
+        ledger_namespace: Option<&AccountRef>,
+        path: &Path,
+    ) -> Result<bool> {
+        let ledger = self.ledger().await?;
+        let chunks = self.chunks().await?;
+        Ok(ledger.count(ledger_namespace, path).await > 0
+            || chunks.is_referred(ledger_namespace, path).await)
+    }
+
+    /// Checks whether the guarantee refers the path.
+    ///
+    /// The objects of a storage which shares them across all accounts, such as IPFS,
+    /// are accessible only to the accounts which refer them in the ledger.
+    async fn verify_account(
+        &self,
+        guarantee: &AccountRef,
+        path: &Path,
+        operation: IpsisOperation,
+    ) -> Result<()> {
+        if self.persistent_storage.use_account_as_namespace()
+            || self.ledger().await?.refers(None, path, guarantee).await
+        {
+            Ok(())
+        } else {
+            Err(PermissionDenied {
+                account: *guarantee,
+                operation,
+            }
+            .into())
+        }
+    }
+
+    /// Resolves the namespace of the ledger entries.
+    ///
+    /// Returns `None` if the persistent storage shares the objects across all accounts.
+    fn ledger_namespace<'a>(&self, namespace: &'a AccountRef) -> Option<&'a AccountRef> {
+        if self.persistent_storage.use_account_as_namespace() {
+            Some(namespace)
+        } else {
+            None
+        }
+    }
+
+    pub async fn get_raw_for(
+        &self,
+        guarantee: &AccountRef,
+        path: &Path,
+    ) -> Result<VerifyingReader<tokio::io::DuplexStream>> {
         // create a channel
         let (mut tx, rx) = tokio::io::duplex(CHUNK_SIZE.min(path.len.try_into()?));
 
         // external call
-        if !self.config.enable_get_next_hop || self.contains(&path).await? {
+        if !self.config.enable_get_next_hop || self.contains_for(guarantee, path).await? {
             // clone the arguments to send over the thread
-            let account_ref = *self.ipiis.account_ref();
-            let path = path.clone();
+            let namespace = self.namespace(guarantee);
+            let path = *path;
             let persistent_storage = self.persistent_storage.clone();
+            self.verify_account(guarantee, &path, IpsisOperation::Get)
+                .await?;
 
-            tokio::spawn(async move {
-                tx.write_u64(path.len).await?;
-                persistent_storage
-                    .get_raw(&account_ref, &path, &mut tx)
-                    .await
-            });
+            match self
+                .chunks()
+                .await?
+                .get_manifest(self.ledger_namespace(&namespace), &path)
+                .await
+            {
+                // reassemble the chunks
+                Some(manifest) => tokio::spawn(async move {
+                    tx.write_u64(path.len).await?;
+                    for chunk in &manifest.chunks {
+                        persistent_storage
+                            .get_raw(&namespace, chunk, &mut tx)
+                            .await?;
+                    }
+                    Result::<_, Error>::Ok(())
+                }),
+                None => tokio::spawn(async move {
+                    tx.write_u64(path.len).await?;
+                    persistent_storage.get_raw(&namespace, &path, &mut tx).await
+                }),
+            };
         } else {
             // traverse to next-hop
             let mut rx = self.ipiis.get_raw(&path).await?;
@@ -115,103 +438,890 @@ where
         }
 
         // pack data
-        Ok(rx)
+        if self.config.enable_verify_on_read && !self.persistent_storage.use_hash_as_native() {
+            Ok(VerifyingReader::new(rx, *path))
+        } else {
+            Ok(VerifyingReader::unverified(rx))
+        }
+    }
+
+    pub async fn get_raw_shared_for(
+        &self,
+        guarantee: &AccountRef,
+        path: &Path,
+        capabilities: &[SignedCapability],
+    ) -> Result<VerifyingReader<tokio::io::DuplexStream>> {
+        // resolve the owner
+        let owner = self
+            .revocations()
+            .await?
+            .verify_chain(guarantee, path, capabilities)
+            .await?;
+
+        self.get_raw_for(&owner, path).await
     }
 
-    async fn put_raw<R>(&self, path: &Path, mut data: R) -> Result<()>
+    pub async fn get_raw_range_for(
+        &self,
+        guarantee: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<VerifyingReader<tokio::io::DuplexStream>> {
+        // validate the range
+        let range = PathRange {
+            path: *path,
+            offset,
+            len,
+        };
+        range.validate()?;
+
+        // create a channel
+        let (mut tx, rx) = tokio::io::duplex(CHUNK_SIZE.min(len.try_into()?).max(1));
+
+        // external call
+        if !self.config.enable_get_next_hop || self.contains_for(guarantee, path).await? {
+            // clone the arguments to send over the thread
+            let namespace = self.namespace(guarantee);
+            let path = *path;
+            let persistent_storage = self.persistent_storage.clone();
+            let verify =
+                self.config.enable_verify_on_read && !self.persistent_storage.use_hash_as_native();
+            self.verify_account(guarantee, &path, IpsisOperation::Get)
+                .await?;
+
+            match self
+                .chunks()
+                .await?
+                .get_manifest(self.ledger_namespace(&namespace), &path)
+                .await
+            {
+                // reassemble the overlapping chunks
+                Some(manifest) => tokio::spawn(async move {
+                    tx.write_u64(len).await?;
+
+                    let end = offset + len;
+                    let mut chunk_offset = 0;
+                    for chunk in &manifest.chunks {
+                        let chunk_end = chunk_offset + chunk.len;
+                        let (start_in_range, end_in_range) =
+                            (offset.max(chunk_offset), end.min(chunk_end));
+                        if start_in_range < end_in_range && verify {
+                            // the chunk is read as a whole, to be verified against its path
+                            let mut data = Vec::with_capacity(chunk.len.try_into()?);
+                            persistent_storage
+                                .get_raw(&namespace, chunk, &mut data)
+                                .await?;
+                            let actual = Path {
+                                value: Hash::with_bytes(&data),
+                                len: data.len() as u64,
+                            };
+                            if actual != *chunk {
+                                return Err(IntegrityError {
+                                    expected: *chunk,
+                                    actual,
+                                }
+                                .into());
+                            }
+
+                            let range = (start_in_range - chunk_offset) as usize
+                                ..(end_in_range - chunk_offset) as usize;
+                            tx.write_all(&data[range]).await?;
+                        } else if start_in_range < end_in_range {
+                            persistent_storage
+                                .get_raw_range(
+                                    &namespace,
+                                    chunk,
+                                    start_in_range - chunk_offset,
+                                    end_in_range - start_in_range,
+                                    &mut tx,
+                                )
+                                .await?;
+                        }
+
+                        chunk_offset = chunk_end;
+                        if chunk_offset >= end {
+                            break;
+                        }
+                    }
+                    Result::<_, Error>::Ok(())
+                }),
+                None => tokio::spawn(async move {
+                    tx.write_u64(len).await?;
+                    persistent_storage
+                        .get_raw_range(&namespace, &path, offset, len, &mut tx)
+                        .await
+                }),
+            };
+        } else {
+            // traverse to next-hop
+            let mut rx = self.ipiis.get_raw_range(path, offset, len).await?;
+            tokio::spawn(async move { tokio::io::copy(&mut rx, &mut tx).await });
+        }
+
+        // pack data
+        Ok(VerifyingReader::unverified(rx))
+    }
+
+    pub async fn put_raw_for<R>(&self, guarantee: &AccountRef, lease: &Lease, data: R) -> Result<()>
     where
         R: AsyncRead + Send + Sync + Unpin + 'static,
     {
-        let result = if <PersistentStorage as IpsisPersistentStorage>::USE_HASH_AS_NATIVE {
-            // external call
-            self.persistent_storage
-                .put_raw(self.ipiis.account_ref(), path, &mut data.take(path.len))
-                .await?
-        } else {
-            // create a channel
-            let (mut tx, mut rx) = tokio::io::duplex(CHUNK_SIZE);
+        let namespace = self.namespace(guarantee);
+        let _lock = self
+            .locks
+            .lock(self.ledger_namespace(&namespace), &lease.path)
+            .await;
 
-            // clone the arguments to send over the thread
-            let total_len = path.len;
+        // external call
+        self.store_raw(&namespace, &lease.path, data).await?;
 
-            // begin digesting a hash
-            let handle_hash = tokio::spawn(async move {
-                let mut chunk = Vec::with_capacity(CHUNK_SIZE.min(total_len.try_into()?));
-                let mut hasher = Hasher::default();
+        // register the reference
+        self.ledger()
+            .await?
+            .add_reference(
+                self.ledger_namespace(&namespace),
+                &lease.path,
+                guarantee,
+                lease.expiration_date.clone(),
+            )
+            .await
+    }
 
-                'pipe: loop {
-                    // clean up buffer
-                    chunk.clear();
-
-                    // read to buffer
-                    let chunk_size = CHUNK_SIZE as u64;
-                    let chunk_size = chunk_size.min(total_len - hasher.len() as u64);
-                    let mut take = (&mut data).take(chunk_size);
-                    take.read_to_end(&mut chunk).await?;
-
-                    let chunk_len = chunk.len();
-                    if chunk_len > 0 {
-                        let ((), tx_result) =
-                            tokio::join!(async { hasher.update(&chunk) }, tx.write_all(&chunk));
-                        tx_result?;
-                    }
+    /// Stores a local file, which the persistent storage imports without copying if possible.
+    pub async fn import_raw_for(
+        &self,
+        guarantee: &AccountRef,
+        local_path: &::std::path::Path,
+        expiration_date: Option<DateTime>,
+    ) -> Result<Path> {
+        let namespace = self.namespace(guarantee);
 
-                    let len = hasher.len() as u64;
-                    if len >= total_len || chunk_len == 0 {
-                        break 'pipe Result::<_, Error>::Ok(Path {
-                            value: hasher.finalize(),
-                            len,
-                        });
-                    }
-                }
-            });
+        // hash the file
+        let path = hash_file(local_path).await?;
+        let _lock = self
+            .locks
+            .lock(self.ledger_namespace(&namespace), &path)
+            .await;
+
+        // external call
+        if !self
+            .persistent_storage
+            .import_file(&namespace, &path, local_path)
+            .await?
+        {
+            // send the file instead
+            let file = File::open(local_path).await?;
+            self.store_raw(&namespace, &path, file).await?;
+        }
+
+        // register the reference
+        self.ledger()
+            .await?
+            .add_reference(
+                self.ledger_namespace(&namespace),
+                &path,
+                guarantee,
+                expiration_date,
+            )
+            .await?;
+        Ok(path)
+    }
+
+    pub async fn begin_put_for(&self, guarantee: &AccountRef, path: &Path) -> Result<u64> {
+        // the sessions are staged on the local disk
+        let max_len = self.config.upload_max_len;
+        if max_len > 0 && path.len > max_len {
+            bail!("the upload is too long: {}/{max_len}", path.len)
+        }
+
+        self.uploads().await?.begin(guarantee, path).await
+    }
+
+    pub async fn put_part_for<R>(
+        &self,
+        guarantee: &AccountRef,
+        range: &PathRange,
+        data: R,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        self.uploads()
+            .await?
+            .write_part(guarantee, range, data)
+            .await
+    }
+
+    pub async fn commit_put_for(&self, guarantee: &AccountRef, lease: &Lease) -> Result<()> {
+        let data = self
+            .uploads()
+            .await?
+            .open_completed(guarantee, &lease.path)
+            .await?;
+
+        // store the received data
+        if let Err(e) = self.put_raw_for(guarantee, lease, data).await {
+            // the received data can never be committed, so the session is closed
+            if e.downcast_ref::<IntegrityError>().is_some() {
+                self.uploads().await?.remove(guarantee, &lease.path).await?;
+            }
+            return Err(e);
+        }
+
+        // close the session
+        self.uploads().await?.remove(guarantee, &lease.path).await
+    }
+
+    pub async fn put_manifest_for(
+        &self,
+        guarantee: &AccountRef,
+        lease: &Lease,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        let namespace = self.namespace(guarantee);
+        let ledger_namespace = self.ledger_namespace(&namespace);
+        let _lock = self.locks.lock(ledger_namespace, &lease.path).await;
+
+        // validate the manifest
+        if manifest.to_path()? != lease.path {
+            bail!("failed to validate the path")
+        }
+        for chunk in &manifest.chunks {
+            if !self.persistent_storage.contains(&namespace, chunk).await? {
+                bail!("the chunk is not stored: {}", chunk.value.to_string())
+            }
+        }
+
+        // the reads are verified by recomputing the manifest, so the boundaries should be canonical
+        if !self.persistent_storage.use_hash_as_native()
+            && self.hash_manifest(&namespace, manifest).await? != *manifest
+        {
+            bail!("the manifest is not canonical")
+        }
+
+        // store the manifest, which has its own length
+        let data = manifest.to_bytes()?;
+        let path_manifest = Path {
+            value: lease.path.value,
+            len: data.len().try_into()?,
+        };
+        if !self
+            .persistent_storage
+            .contains(&namespace, &path_manifest)
+            .await?
+        {
+            self.store_raw(&namespace, &path_manifest, Cursor::new(data))
+                .await?;
+        }
+
+        // register the chunks and the reference
+        self.chunks()
+            .await?
+            .add_manifest(ledger_namespace, &lease.path, manifest)
+            .await?;
+        self.ledger()
+            .await?
+            .add_reference(
+                ledger_namespace,
+                &lease.path,
+                guarantee,
+                lease.expiration_date.clone(),
+            )
+            .await
+    }
+
+    /// Stores the data into the persistent storage, validating its hash.
+    async fn store_raw<R>(&self, namespace: &AccountRef, path: &Path, data: R) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // the stored path and the path from the data, if they mismatch the requested path
+        let result = if self.persistent_storage.use_hash_as_native() {
+            // external call
+            self.persistent_storage
+                .put_raw(namespace, path, &mut data.take(path.len))
+                .await?
+                .map_err(|path_stored| (path_stored, path_stored))
+        } else if self.persistent_storage.contains(namespace, path).await? {
+            // keep the stored object, which may not be clobbered by the unverified data
+            return self.verify_raw(path, data.take(path.len)).await;
+        } else {
+            // digest a hash while storing, failing the request if the data are broken or truncated
+            let mut reader = HashingReader::new(data.take(path.len), path.len);
 
             // external call
             match self
                 .persistent_storage
-                .put_raw(self.ipiis.account_ref(), path, &mut rx)
+                .put_raw(namespace, path, &mut reader)
                 .await?
             {
                 Ok(()) => {
-                    // poll hash
-                    let path_from_data = handle_hash.await??;
+                    // the storage may not have read the data until the end
+                    let path_from_data = reader.finalize();
 
+                    // the object has been stored under the requested path
                     if path == &path_from_data {
                         Ok(())
                     } else {
-                        Err(path_from_data)
+                        Err((*path, path_from_data))
                     }
                 }
-                Err(path_from_data) => Err(path_from_data),
+                Err(path_stored) => Err((path_stored, path_stored)),
             }
         };
 
         // validate hash
         match result {
             Ok(()) => Ok(()),
-            Err(path_from_data) => {
-                // revert the request
-                self.delete(&path_from_data).await?;
+            Err((path_stored, actual)) => {
+                // revert the request, unless the stored object is referred by others
+                // the lock of the requested path is already held by the caller
+                let ledger_namespace = self.ledger_namespace(namespace);
+                let _lock = if path_stored.value == path.value {
+                    None
+                } else {
+                    Some(self.locks.lock(ledger_namespace, &path_stored).await)
+                };
+                if !self.is_referred(ledger_namespace, &path_stored).await? {
+                    self.persistent_storage
+                        .delete(namespace, &path_stored)
+                        .await?;
+                }
 
                 // raise an error
-                bail!("failed to validate the path")
+                Err(IntegrityError {
+                    expected: *path,
+                    actual,
+                }
+                .into())
             }
         }
     }
 
-    async fn contains(&self, path: &Path) -> Result<bool> {
+    pub async fn renew_for(&self, guarantee: &AccountRef, lease: &Lease) -> Result<()> {
+        let namespace = self.namespace(guarantee);
+
+        self.ledger()
+            .await?
+            .renew(
+                self.ledger_namespace(&namespace),
+                &lease.path,
+                guarantee,
+                lease.expiration_date.clone(),
+            )
+            .await
+    }
+
+    pub async fn contains_for(&self, guarantee: &AccountRef, path: &Path) -> Result<bool> {
+        let namespace = self.namespace(guarantee);
+        if self
+            .verify_account(guarantee, path, IpsisOperation::Contains)
+            .await
+            .is_err()
+        {
+            return Ok(false);
+        }
+
+        // external call
+        self.persistent_storage.contains(&namespace, path).await
+    }
+
+    pub async fn stat_for(&self, guarantee: &AccountRef, path: &Path) -> Result<Stat> {
+        // external call
+        if !self.config.enable_get_next_hop || self.contains_for(guarantee, path).await? {
+            let namespace = self.namespace(guarantee);
+            self.verify_account(guarantee, path, IpsisOperation::Stat)
+                .await?;
+            let mut stat = self.persistent_storage.stat(&namespace, path).await?;
+
+            // the manifest is stored with its own length
+            if self
+                .chunks()
+                .await?
+                .get_manifest(self.ledger_namespace(&namespace), path)
+                .await
+                .is_some()
+            {
+                stat.path = *path;
+            }
+            Ok(stat)
+        } else {
+            // traverse to next-hop
+            self.ipiis.stat(path).await
+        }
+    }
+
+    pub async fn delete_for(&self, guarantee: &AccountRef, path: &Path) -> Result<()> {
+        let namespace = self.namespace(guarantee);
+        let _lock = self
+            .locks
+            .lock(self.ledger_namespace(&namespace), path)
+            .await;
+
+        // release the reference
+        let remaining = self
+            .ledger()
+            .await?
+            .remove_reference(self.ledger_namespace(&namespace), path, guarantee)
+            .await?;
+
+        // external call, only if no one refers the data anymore
+        if remaining == 0 {
+            self.delete_unreferred(&namespace, path).await.map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Deletes the object whose references have been released, including the chunks of a manifest.
+    ///
+    /// The caller should hold the lock of the path.
+    /// Returns `false` if the object is kept as a chunk of another manifest.
+    async fn delete_unreferred(&self, namespace: &AccountRef, path: &Path) -> Result<bool> {
+        let ledger_namespace = self.ledger_namespace(namespace);
+        let chunks = self.chunks().await?;
+        let ledger = self.ledger().await?;
+        if chunks.is_referred(ledger_namespace, path).await {
+            return Ok(false);
+        }
+
+        // release the chunks
+        let released = chunks
+            .remove_manifest(ledger_namespace, path)
+            .await?
+            .unwrap_or_default();
+
         // external call
-        self.persistent_storage
-            .contains(self.ipiis.account_ref(), path)
+        self.persistent_storage.delete(namespace, path).await?;
+        for chunk in released {
+            let _lock = self.locks.lock(ledger_namespace, &chunk).await;
+            if ledger.count(ledger_namespace, &chunk).await == 0
+                && !chunks.is_referred(ledger_namespace, &chunk).await
+            {
+                self.persistent_storage.delete(namespace, &chunk).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    pub async fn list_for(&self, guarantee: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        let namespace = self.namespace(guarantee);
+
+        // the storages which share the objects list only the ones which the guarantee refers
+        if !self.persistent_storage.use_account_as_namespace() {
+            return self.list_referred(&namespace, guarantee, query).await;
+        }
+
+        // external call
+        self.persistent_storage.list(&namespace, query).await
+    }
+
+    /// Lists the paths which the guarantee refers in the ledger, ordered by their hashes.
+    async fn list_referred(
+        &self,
+        namespace: &AccountRef,
+        guarantee: &AccountRef,
+        query: &ListQuery,
+    ) -> Result<ListPage> {
+        // collect the paths after the cursor
+        let mut paths: Vec<_> = self
+            .ledger()
+            .await?
+            .paths_of(None, guarantee)
+            .await
+            .into_iter()
+            .map(|path| (path.value.to_string(), path))
+            .filter(|(key, _)| !matches!(&query.cursor, Some(cursor) if key <= cursor))
+            .collect();
+        paths.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        // paginate
+        let limit = query.limit();
+        let next_cursor = if paths.len() > limit {
+            paths.truncate(limit);
+            paths.last().map(|(key, _)| key.clone())
+        } else {
+            None
+        };
+
+        // the references which were recorded before the leases were introduced have no lengths
+        let paths = try_join_all(paths.into_iter().map(|(_, path)| async move {
+            if path.len == 0 {
+                // external call
+                self.persistent_storage
+                    .stat(namespace, &path)
+                    .await
+                    .map(|stat| stat.path)
+            } else {
+                Ok(path)
+            }
+        }))
+        .await?;
+        Ok(ListPage { paths, next_cursor })
+    }
+
+    pub async fn revoke_for(&self, guarantee: &AccountRef, revocation: &Revocation) -> Result<()> {
+        self.revocations()
+            .await?
+            .revoke(guarantee, &revocation.capability)
             .await
     }
 
-    async fn delete(&self, path: &Path) -> Result<()> {
+    /// Releases the expired leases, deletes the objects which are no longer referred,
+    /// and compacts the journals of the bookkeeping.
+    ///
+    /// Returns the number of the deleted objects.
+    pub async fn collect_garbage(&self) -> Result<usize> {
+        let now = DateTime::now();
+
+        // close the stale upload sessions
+        if self.config.upload_session_ttl_secs > 0 {
+            let ttl = Duration::from_secs(self.config.upload_session_ttl_secs);
+            if let Err(e) = self.uploads().await?.remove_stale(ttl).await {
+                warn!("failed to close the stale upload sessions: {e}");
+            }
+        }
+
+        let released = self.ledger().await?.release_expired(&now).await?;
+        let mut num_deleted = 0;
+        for ExpiredReference {
+            namespace,
+            path,
+            owner,
+        } in released
+        {
+            // the global namespace ignores the account, so the owner can be used instead
+            let namespace = namespace.unwrap_or(owner);
+
+            // the path may have been referred again since its lease was released
+            let ledger_namespace = self.ledger_namespace(&namespace);
+            let _lock = self.locks.lock(ledger_namespace, &path).await;
+            if self.ledger().await?.count(ledger_namespace, &path).await > 0 {
+                continue;
+            }
+
+            // external call
+            match self.delete_unreferred(&namespace, &path).await {
+                Ok(true) => num_deleted += 1,
+                Ok(false) => {}
+                Err(e) => warn!(
+                    "failed to collect the expired object {}: {e}",
+                    path.value.to_string(),
+                ),
+            }
+        }
+
+        // drop the released records from the journals
+        self.ledger().await?.compact().await?;
+        self.chunks().await?.compact().await?;
+        Ok(num_deleted)
+    }
+
+    /// Re-verifies every stored object against the path which is derived from its storage key.
+    ///
+    /// The namespaces are discovered from the persistent storage, and the objects which are
+    /// referred by nobody are reported as orphaned, including the ones stored before the ledger
+    /// was introduced, so they are quarantined only if requested explicitly.
+    /// The quarantined objects are moved into the quarantine directory, so they can be restored.
+    pub async fn scrub_storage(&self, query: &ScrubQuery) -> Result<ScrubReport> {
+        let scrubber = self.scrubber().await?;
+        let _guard = scrubber.lock()?;
+
+        let mut namespaces = self.storage_namespaces().await?;
+
+        // resume from the checkpoint
+        let mut checkpoint = if query.resume {
+            scrubber.load_checkpoint().await?
+        } else {
+            scrubber.clear_checkpoint().await?;
+            None
+        };
+        if let Some(checkpoint) = &checkpoint {
+            let begin = checkpoint.namespace.to_string();
+            namespaces.retain(|namespace| namespace.to_string() >= begin);
+        }
+
+        let mut report = ScrubReport::default();
+        let mut limiter = ScrubRateLimiter::new(self.config.scrub_rate_limit);
+        for namespace in namespaces {
+            let mut cursor = match checkpoint.take() {
+                Some(checkpoint) if checkpoint.namespace == namespace => checkpoint.cursor,
+                _ => None,
+            };
+
+            loop {
+                // stop if the limit is reached
+                let limit = match query.limit {
+                    Some(limit) if report.num_checked >= limit => {
+                        let checkpoint = ScrubCheckpoint { namespace, cursor };
+                        scrubber.save_checkpoint(&checkpoint).await?;
+                        return Ok(report);
+                    }
+                    Some(limit) => (limit - report.num_checked).min(LIST_LIMIT_MAX.into()) as u32,
+                    None => LIST_LIMIT_MAX,
+                };
+
+                // external call
+                let query_page = ListQuery {
+                    cursor: cursor.clone(),
+                    limit,
+                };
+                let page = self
+                    .persistent_storage
+                    .list(&namespace, &query_page)
+                    .await?;
+                for path in page.paths {
+                    self.scrub_object(query, &namespace, &path, &mut report)
+                        .await?;
+                    limiter.consume(path.len).await;
+                }
+
+                // save the progress
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+                let checkpoint = ScrubCheckpoint {
+                    namespace,
+                    cursor: cursor.clone(),
+                };
+                scrubber.save_checkpoint(&checkpoint).await?;
+            }
+        }
+
+        scrubber.clear_checkpoint().await?;
+        report.completed = true;
+        Ok(report)
+    }
+
+    /// Restores the missing redundant copies of the stored objects (e.g. on the replicas).
+    ///
+    /// Returns the number of the restored copies.
+    pub async fn repair_storage(&self) -> Result<usize> {
+        let mut num_restored = 0;
+        for namespace in self.storage_namespaces().await? {
+            num_restored += self.persistent_storage.repair(&namespace).await?;
+        }
+        Ok(num_restored)
+    }
+
+    /// Returns the namespaces of the persistent storage in a stable order.
+    async fn storage_namespaces(&self) -> Result<Vec<AccountRef>> {
+        let mut namespaces = if self.persistent_storage.use_account_as_namespace() {
+            self.persistent_storage.namespaces().await?
+        } else {
+            vec![]
+        };
+        namespaces.push(*self.ipiis.account_ref());
+        namespaces.sort_by_cached_key(ToString::to_string);
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+
+    async fn scrub_object(
+        &self,
+        query: &ScrubQuery,
+        namespace: &AccountRef,
+        path: &Path,
+        report: &mut ScrubReport,
+    ) -> Result<()> {
+        report.num_checked += 1;
+        report.num_bytes += path.len;
+
+        // validate the object
+        let (reason, entries, quarantine) = match self.hash_object(namespace, path).await {
+            Ok(path_from_data) if &path_from_data == path => {
+                if self.is_orphaned(namespace, path).await? {
+                    (
+                        "referred by nobody".into(),
+                        &mut report.orphaned,
+                        query.quarantine_orphans,
+                    )
+                } else {
+                    return Ok(());
+                }
+            }
+            Ok(path_from_data) if path_from_data.len != path.len => (
+                format!("length mismatch: {}", path_from_data.len),
+                &mut report.corrupted,
+                query.quarantine,
+            ),
+            Ok(path_from_data) => (
+                format!("hash mismatch: {}", path_from_data.value.to_string()),
+                &mut report.corrupted,
+                query.quarantine,
+            ),
+            Err(e) => (
+                format!("failed to read: {e}"),
+                &mut report.corrupted,
+                query.quarantine,
+            ),
+        };
+        warn!(
+            "found a bad object {} in {}: {reason}",
+            path.value.to_string(),
+            namespace.to_string(),
+        );
+
+        // quarantine the object
+        let quarantined = quarantine
+            && match self.quarantine(namespace, path).await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(
+                        "failed to quarantine the object {}: {e}",
+                        path.value.to_string(),
+                    );
+                    false
+                }
+            };
+
+        entries.push(ScrubEntry {
+            namespace: *namespace,
+            path: *path,
+            reason,
+            quarantined,
+        });
+        Ok(())
+    }
+
+    /// Reads the data without storing it, and checks whether it is derived into the path.
+    async fn verify_raw<R>(&self, path: &Path, mut data: R) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut hasher = Hasher::default();
+        loop {
+            let len = data.read(&mut chunk).await?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&chunk[..len]);
+        }
+
+        let actual = Path {
+            len: hasher.len() as u64,
+            value: hasher.finalize(),
+        };
+        if &actual == path {
+            Ok(())
+        } else {
+            Err(IntegrityError {
+                expected: *path,
+                actual,
+            }
+            .into())
+        }
+    }
+
+    /// Reads the stored object, and returns the path which is derived from its data.
+    async fn hash_object(&self, namespace: &AccountRef, path: &Path) -> Result<Path> {
+        // create a channel
+        let (mut tx, mut rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        // external call
+        let storage = &self.persistent_storage;
+        let (result_get, result_hash) = tokio::join!(
+            async move { storage.get_raw(namespace, path, &mut tx).await },
+            async move {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let mut hasher = Hasher::default();
+                loop {
+                    let len = rx.read(&mut chunk).await?;
+                    if len == 0 {
+                        break Result::<_, Error>::Ok(hasher);
+                    }
+                    hasher.update(&chunk[..len]);
+                }
+            },
+        );
+        result_get?;
+        let hasher = result_hash?;
+
+        Ok(Path {
+            len: hasher.len() as u64,
+            // the native hashes are verified by the persistent storage itself
+            value: if self.persistent_storage.use_hash_as_native() {
+                path.value
+            } else {
+                hasher.finalize()
+            },
+        })
+    }
+
+    /// Recomputes the manifest of the chunks, as [`Chunker`](ipsis_common::chunk::Chunker) would.
+    async fn hash_manifest(&self, namespace: &AccountRef, manifest: &Manifest) -> Result<Manifest> {
+        // create a channel
+        let (mut tx, mut rx) = tokio::io::duplex(CHUNK_SIZE);
+
         // external call
-        self.persistent_storage
-            .delete(self.ipiis.account_ref(), path)
+        let storage = &self.persistent_storage;
+        let (result_get, result_hash) = tokio::join!(
+            async move {
+                for chunk in &manifest.chunks {
+                    storage.get_raw(namespace, chunk, &mut tx).await?;
+                }
+                Result::<_, Error>::Ok(())
+            },
+            async move {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let mut hasher = ManifestHasher::default();
+                loop {
+                    let len = rx.read(&mut chunk).await?;
+                    if len == 0 {
+                        break Result::<_, Error>::Ok(hasher);
+                    }
+                    hasher.update(&chunk[..len]);
+                }
+            },
+        );
+        result_get?;
+        Ok(result_hash?.finalize())
+    }
+
+    /// Checks whether the object is referred by neither a lease nor a manifest.
+    async fn is_orphaned(&self, namespace: &AccountRef, path: &Path) -> Result<bool> {
+        let ledger_namespace = self.ledger_namespace(namespace);
+        if self.is_referred(ledger_namespace, path).await? {
+            return Ok(false);
+        }
+
+        // the recent objects may be referred right after being stored,
+        // so the objects of unknown age are not orphaned
+        let stat = self.persistent_storage.stat(namespace, path).await?;
+        let threshold: DateTime =
+            (Utc::now() - chrono::Duration::seconds(ORPHAN_GRACE_SECS)).into();
+        Ok(matches!(stat.created_date, Some(date) if date <= threshold))
+    }
+
+    /// Moves the object into the quarantine directory.
+    async fn quarantine(&self, namespace: &AccountRef, path: &Path) -> Result<()> {
+        let path_quarantine = self
+            .scrubber()
+            .await?
+            .to_path_quarantine(namespace, path)
+            .await?;
+
+        // copy the object
+        let mut file = File::create(&path_quarantine).await?;
+        if let Err(e) = self
+            .persistent_storage
+            .get_raw(namespace, path, &mut file)
             .await
+        {
+            tokio::fs::remove_file(&path_quarantine).await.ok();
+            return Err(e);
+        }
+        file.sync_all().await?;
+
+        // external call
+        self.persistent_storage.delete(namespace, path).await
     }
 }
 
 const CHUNK_SIZE: usize = 4_096;
+
+/// The age of the objects which can be reported as orphaned, in seconds.
+const ORPHAN_GRACE_SECS: i64 = 3_600;
@@ -2,12 +2,32 @@ use ipis::env::infer;
 
 pub struct IpsisClientConfig {
     pub enable_get_next_hop: bool,
+    /// Stores every request in the server's own namespace instead of the requesting account's.
+    pub enable_shared_namespace: bool,
+    /// Verifies the hash of the data while reading them.
+    ///
+    /// It is ignored if the persistent storage uses its native hashes (e.g. IPFS CIDs) as paths.
+    pub enable_verify_on_read: bool,
+    /// The interval of collecting the expired objects, in seconds. `0` disables the collection.
+    pub gc_interval_secs: u64,
+    /// The maximum bytes per second to read while scrubbing. `0` disables the limit.
+    pub scrub_rate_limit: u64,
+    /// The maximum length of each upload, in bytes. `0` disables the limit.
+    pub upload_max_len: u64,
+    /// The lifetime of the idle upload sessions, in seconds. `0` keeps them forever.
+    pub upload_session_ttl_secs: u64,
 }
 
 impl Default for IpsisClientConfig {
     fn default() -> Self {
         Self {
             enable_get_next_hop: infer("ipsis_enable_get_next_hop").unwrap_or(true),
+            enable_shared_namespace: infer("ipsis_enable_shared_namespace").unwrap_or(false),
+            enable_verify_on_read: infer("ipsis_enable_verify_on_read").unwrap_or(true),
+            gc_interval_secs: infer("ipsis_gc_interval_secs").unwrap_or(3_600),
+            scrub_rate_limit: infer("ipsis_scrub_rate_limit").unwrap_or(0),
+            upload_max_len: infer("ipsis_upload_max_len").unwrap_or(4 << 30),
+            upload_session_ttl_secs: infer("ipsis_upload_session_ttl_secs").unwrap_or(86_400),
         }
     }
 }
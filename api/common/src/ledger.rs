@@ -0,0 +1,502 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{bail, Error, Result},
+        chrono::{self, Utc},
+        value::chrono::DateTime,
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::sync::Mutex,
+};
+use ipsis_api_persistent_common::journal::{IpsisJournal, JournalKey, JournalTokens};
+
+/// A per-path ledger of the accounts which refer the stored objects.
+///
+/// Every change is appended to a journal file, which is replayed and compacted on startup
+/// and periodically by the garbage collector.
+pub struct IpsisLedger {
+    state: Mutex<LedgerState>,
+}
+
+struct LedgerState {
+    entries: HashMap<LedgerKey, LedgerEntry>,
+    journal: IpsisJournal,
+}
+
+type LedgerKey = JournalKey;
+
+#[derive(Clone, Debug, Default)]
+struct LedgerEntry {
+    len: u64,
+    /// The owners and the expiration dates of their leases.
+    owners: HashMap<AccountRef, Option<DateTime>>,
+}
+
+/// The last reference of a path, which has been released as its lease was expired.
+#[derive(Clone, Debug)]
+pub struct ExpiredReference {
+    pub namespace: Option<AccountRef>,
+    pub path: Path,
+    pub owner: AccountRef,
+}
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisLedger {
+    type GenesisArgs = PathBuf;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let path = infer("ipsis_client_ledger_path").or_else(|e| {
+            let mut path = ::dirs::home_dir().ok_or(e)?;
+            path.push(".ipsis");
+            path.push("ledger");
+            Result::<_, Error>::Ok(path)
+        })?;
+        Self::genesis(path).await
+    }
+
+    async fn genesis(
+        path: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::open(path).await
+    }
+}
+
+impl IpsisLedger {
+    async fn open(path: PathBuf) -> Result<Self> {
+        let (journal, records) = IpsisJournal::open(path).await?;
+
+        // replay the journal
+        let mut entries: HashMap<_, LedgerEntry> = HashMap::default();
+        for record in records {
+            match record {
+                Record::Add {
+                    key,
+                    len,
+                    owner,
+                    expiration_date,
+                } => {
+                    let entry = entries.entry(key).or_default();
+                    entry.len = len;
+                    entry.owners.insert(owner, expiration_date);
+                }
+                Record::Remove { key, owner } => {
+                    if let Some(entry) = entries.get_mut(&key) {
+                        entry.owners.remove(&owner);
+                        if entry.owners.is_empty() {
+                            entries.remove(&key);
+                        }
+                    }
+                }
+            }
+        }
+
+        // compact the journal
+        let mut state = LedgerState { entries, journal };
+        state.compact().await?;
+
+        Ok(Self {
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Rewrites the journal with the current references, dropping the released ones.
+    pub async fn compact(&self) -> Result<()> {
+        self.state.lock().await.compact().await
+    }
+
+    /// Returns the number of the accounts which refer the path.
+    pub async fn count(&self, namespace: Option<&AccountRef>, path: &Path) -> usize {
+        let key = LedgerKey::new(namespace, path);
+
+        let state = self.state.lock().await;
+        state
+            .entries
+            .get(&key)
+            .map(|entry| entry.owners.len())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the owner refers the path.
+    pub async fn refers(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+        owner: &AccountRef,
+    ) -> bool {
+        let key = LedgerKey::new(namespace, path);
+
+        let state = self.state.lock().await;
+        state
+            .entries
+            .get(&key)
+            .map(|entry| entry.owners.contains_key(owner))
+            .unwrap_or_default()
+    }
+
+    /// Returns the paths which the owner refers in the namespace.
+    pub async fn paths_of(&self, namespace: Option<&AccountRef>, owner: &AccountRef) -> Vec<Path> {
+        let state = self.state.lock().await;
+        state
+            .entries
+            .iter()
+            .filter(|(key, entry)| {
+                key.namespace.as_ref() == namespace && entry.owners.contains_key(owner)
+            })
+            .map(|(key, entry)| Path {
+                value: key.hash,
+                len: entry.len,
+            })
+            .collect()
+    }
+
+    /// Registers the owner as a referrer of the path.
+    ///
+    /// If the owner already refers the path, the lease is replaced.
+    pub async fn add_reference(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+        owner: &AccountRef,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()> {
+        let key = LedgerKey::new(namespace, path);
+
+        let mut state = self.state.lock().await;
+        state.insert(key, path.len, *owner, expiration_date).await
+    }
+
+    /// Replaces the lease of the owner's reference.
+    pub async fn renew(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+        owner: &AccountRef,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()> {
+        let key = LedgerKey::new(namespace, path);
+
+        let mut state = self.state.lock().await;
+        let len = match state.entries.get(&key) {
+            Some(entry) if entry.owners.contains_key(owner) => entry.len,
+            _ => bail!(
+                "the account does not refer the path: {}",
+                path.value.to_string()
+            ),
+        };
+        state.insert(key, len, *owner, expiration_date).await
+    }
+
+    /// Releases the owner's reference of the path, and returns the number of remaining references.
+    ///
+    /// The paths which were stored before the ledger was introduced have no entries.
+    /// They can be released only in the owner's own namespace;
+    /// in a shared namespace, they are treated as not being owned by the account.
+    pub async fn remove_reference(
+        &self,
+        namespace: Option<&AccountRef>,
+        path: &Path,
+        owner: &AccountRef,
+    ) -> Result<usize> {
+        let key = LedgerKey::new(namespace, path);
+
+        let mut state = self.state.lock().await;
+        let entry = match state.entries.get(&key) {
+            Some(entry) => entry,
+            None if namespace == Some(owner) => return Ok(0),
+            None => bail!(
+                "the account does not refer the path: {}",
+                path.value.to_string()
+            ),
+        };
+        if !entry.owners.contains_key(owner) {
+            bail!(
+                "the account does not refer the path: {}",
+                path.value.to_string()
+            );
+        }
+
+        // write to the journal
+        let record = Record::Remove { key, owner: *owner };
+        state.write(&record).await?;
+
+        // apply to the entries
+        let entry = state.entries.get_mut(&key).unwrap();
+        entry.owners.remove(owner);
+
+        let remaining = entry.owners.len();
+        if remaining == 0 {
+            state.entries.remove(&key);
+        }
+        Ok(remaining)
+    }
+
+    /// Releases the references whose leases have been expired until the given date,
+    /// and returns the paths which are no longer referred by anyone.
+    pub async fn release_expired(&self, now: &DateTime) -> Result<Vec<ExpiredReference>> {
+        let mut state = self.state.lock().await;
+
+        // find the expired references
+        let expired: Vec<_> = state
+            .entries
+            .iter()
+            .flat_map(|(key, entry)| {
+                entry
+                    .owners
+                    .iter()
+                    .filter(|(_, lease)| matches!(lease, Some(date) if date <= now))
+                    .map(|(owner, _)| (*key, *owner))
+            })
+            .collect();
+
+        let mut released = vec![];
+        for (key, owner) in expired {
+            // write to the journal
+            let record = Record::Remove { key, owner };
+            state.write(&record).await?;
+
+            // apply to the entries
+            let entry = state.entries.get_mut(&key).unwrap();
+            entry.owners.remove(&owner);
+
+            if entry.owners.is_empty() {
+                let len = entry.len;
+                state.entries.remove(&key);
+
+                released.push(ExpiredReference {
+                    namespace: key.namespace,
+                    path: Path {
+                        value: key.hash,
+                        len,
+                    },
+                    owner,
+                });
+            }
+        }
+        Ok(released)
+    }
+}
+
+impl LedgerState {
+    async fn insert(
+        &mut self,
+        key: LedgerKey,
+        len: u64,
+        owner: AccountRef,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()> {
+        let is_same = self
+            .entries
+            .get(&key)
+            .and_then(|entry| entry.owners.get(&owner))
+            .map(|lease| lease == &expiration_date)
+            .unwrap_or_default();
+        if is_same {
+            return Ok(());
+        }
+
+        // write to the journal
+        let record = Record::Add {
+            key,
+            len,
+            owner,
+            expiration_date: expiration_date.clone(),
+        };
+        self.write(&record).await?;
+
+        // apply to the entries
+        let entry = self.entries.entry(key).or_default();
+        entry.len = len;
+        entry.owners.insert(owner, expiration_date);
+        Ok(())
+    }
+
+    async fn compact(&mut self) -> Result<()> {
+        let records = self.entries.iter().flat_map(|(key, entry)| {
+            entry
+                .owners
+                .iter()
+                .map(move |(owner, expiration_date)| Record::Add {
+                    key: *key,
+                    len: entry.len,
+                    owner: *owner,
+                    expiration_date: expiration_date.clone(),
+                })
+        });
+        self.journal.compact(records).await
+    }
+
+    /// Appends the record durably, as the objects may be deleted by trusting it.
+    async fn write(&mut self, record: &Record) -> Result<()> {
+        self.journal.append(record).await?;
+        self.journal.sync().await
+    }
+}
+
+enum Record {
+    Add {
+        key: LedgerKey,
+        len: u64,
+        owner: AccountRef,
+        expiration_date: Option<DateTime>,
+    },
+    Remove {
+        key: LedgerKey,
+        owner: AccountRef,
+    },
+}
+
+/// The placeholder of the lease which never expires.
+const LEASE_PERMANENT: &str = "-";
+
+impl ::core::fmt::Display for Record {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match self {
+            Self::Add {
+                key,
+                len,
+                owner,
+                expiration_date,
+            } => {
+                write!(f, "+ {key} {len} {}", owner.to_string())?;
+                match expiration_date {
+                    Some(date) => writeln!(f, " {}", date.to_rfc3339()),
+                    None => writeln!(f, " {LEASE_PERMANENT}"),
+                }
+            }
+            Self::Remove { key, owner } => writeln!(f, "- {key} {}", owner.to_string()),
+        }
+    }
+}
+
+impl FromStr for Record {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut tokens = JournalTokens::new("ledger", line);
+
+        let op = tokens.next_token()?;
+        let key = tokens.next_key()?;
+
+        match op {
+            // the records before the leases were introduced: `+ <namespace> <hash> <owner>`
+            "+" if tokens.remaining() == 1 => Ok(Self::Add {
+                key,
+                len: 0,
+                owner: tokens.next_token()?.parse()?,
+                expiration_date: None,
+            }),
+            "+" => Ok(Self::Add {
+                key,
+                len: tokens.next_token()?.parse()?,
+                owner: tokens.next_token()?.parse()?,
+                expiration_date: match tokens.next_token()? {
+                    LEASE_PERMANENT => None,
+                    date => Some(
+                        chrono::DateTime::parse_from_rfc3339(date)?
+                            .with_timezone(&Utc)
+                            .into(),
+                    ),
+                },
+            }),
+            "-" => Ok(Self::Remove {
+                key,
+                owner: tokens.next_token()?.parse()?,
+            }),
+            op => bail!("unknown ledger operation: {op:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipis::{
+        core::{account::Account, value::hash::Hash},
+        tokio,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_legacy_records() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let path_ledger = dir.path().join("ledger");
+
+        let owner = Account::generate().account_ref();
+        let path = Path {
+            value: Hash::with_bytes(b"legacy"),
+            len: 6,
+        };
+        let key = LedgerKey::new(Some(&owner), &path);
+
+        // the ledger which has been written before the leases were introduced
+        tokio::fs::write(&path_ledger, format!("+ {key} {}\n", owner.to_string())).await?;
+
+        let ledger = IpsisLedger::open(path_ledger.clone()).await?;
+        assert_eq!(ledger.count(Some(&owner), &path).await, 1);
+        ledger.renew(Some(&owner), &path, &owner, None).await?;
+        drop(ledger);
+
+        // the compacted ledger is written in the current format
+        let ledger = IpsisLedger::open(path_ledger).await?;
+        assert_eq!(ledger.count(Some(&owner), &path).await, 1);
+        assert_eq!(
+            ledger.remove_reference(Some(&owner), &path, &owner).await?,
+            0
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paths_of_owner() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let ledger = IpsisLedger::open(dir.path().join("ledger")).await?;
+
+        let owner = Account::generate().account_ref();
+        let other = Account::generate().account_ref();
+        let path = Path {
+            value: Hash::with_bytes(b"owned"),
+            len: 5,
+        };
+        let path_other = Path {
+            value: Hash::with_bytes(b"other"),
+            len: 5,
+        };
+        ledger.add_reference(None, &path, &owner, None).await?;
+        ledger.add_reference(None, &path_other, &other, None).await?;
+
+        assert_eq!(ledger.paths_of(None, &owner).await, [path]);
+        assert!(ledger.paths_of(Some(&owner), &owner).await.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_unknown_reference() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let ledger = IpsisLedger::open(dir.path().join("ledger")).await?;
+
+        let owner = Account::generate().account_ref();
+        let path = Path {
+            value: Hash::with_bytes(b"legacy"),
+            len: 6,
+        };
+
+        // the legacy objects in a shared namespace are owned by nobody
+        assert!(ledger.remove_reference(None, &path, &owner).await.is_err());
+
+        // the legacy objects in the owner's namespace are owned by the owner
+        assert_eq!(
+            ledger.remove_reference(Some(&owner), &path, &owner).await?,
+            0
+        );
+        Ok(())
+    }
+}
@@ -1,2 +1,10 @@
+pub mod capability;
+pub mod chunk;
 pub mod client;
 pub mod config;
+pub mod ledger;
+pub mod lock;
+pub mod policy;
+pub mod scrub;
+pub mod upload;
+pub mod verify;
@@ -0,0 +1,80 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
+use ipis::{
+    core::account::AccountRef,
+    path::Path,
+    tokio::sync::{Mutex, OwnedMutexGuard},
+};
+use ipsis_api_persistent_common::journal::JournalKey;
+
+/// The locks of the paths, which keep a reference from being added while its object is deleted.
+///
+/// The lock of a path should be held from checking the ledger until the object is stored or
+/// deleted.
+#[derive(Default)]
+pub struct IpsisPathLocks {
+    locks: ::std::sync::Mutex<HashMap<JournalKey, Weak<Mutex<()>>>>,
+}
+
+impl IpsisPathLocks {
+    pub async fn lock(&self, namespace: Option<&AccountRef>, path: &Path) -> OwnedMutexGuard<()> {
+        let key = JournalKey::new(namespace, path);
+
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+
+            // forget the locks which are no longer held
+            locks.retain(|_, lock| lock.strong_count() > 0);
+
+            match locks.get(&key).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::default();
+                    locks.insert(key, Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ipis::{core::value::hash::Hash, tokio};
+
+    use super::*;
+
+    fn path(data: &[u8]) -> Path {
+        Path {
+            value: Hash::with_bytes(data),
+            len: data.len() as u64,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lock_same_path() {
+        let locks = IpsisPathLocks::default();
+        let (a, b) = (path(b"a"), path(b"b"));
+
+        let guard = locks.lock(None, &a).await;
+
+        // the other paths are not blocked
+        drop(locks.lock(None, &b).await);
+
+        // the same path is blocked until the guard is dropped
+        let timeout = Duration::from_millis(50);
+        assert!(tokio::time::timeout(timeout, locks.lock(None, &a))
+            .await
+            .is_err());
+        drop(guard);
+        assert!(tokio::time::timeout(timeout, locks.lock(None, &a))
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{account::AccountRef, anyhow::Result},
+    env::{infer, Infer},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IpsisOperation {
+    Get,
+    Put,
+    Contains,
+    Stat,
+    Delete,
+    List,
+    Revoke,
+    Scrub,
+}
+
+impl IpsisOperation {
+    pub const ALL: [Self; 8] = [
+        Self::Get,
+        Self::Put,
+        Self::Contains,
+        Self::Stat,
+        Self::Delete,
+        Self::List,
+        Self::Revoke,
+        Self::Scrub,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "get",
+            Self::Put => "put",
+            Self::Contains => "contains",
+            Self::Stat => "stat",
+            Self::Delete => "delete",
+            Self::List => "list",
+            Self::Revoke => "revoke",
+            Self::Scrub => "scrub",
+        }
+    }
+
+    /// Whether the operation modifies the storage.
+    pub fn is_write(&self) -> bool {
+        matches!(self, Self::Put | Self::Delete | Self::Revoke)
+    }
+
+    /// Whether the operation manages the whole storage rather than the account's objects.
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Self::Scrub)
+    }
+}
+
+/// An error which is raised when the policy denies a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PermissionDenied {
+    pub account: AccountRef,
+    pub operation: IpsisOperation,
+}
+
+impl ::core::fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        write!(
+            f,
+            "permission denied: {:?} is not allowed to {}",
+            self.account.to_string(),
+            self.operation.as_str(),
+        )
+    }
+}
+
+impl ::std::error::Error for PermissionDenied {}
+
+pub trait IpsisPolicy {
+    fn authorize(
+        &self,
+        account: &AccountRef,
+        operation: IpsisOperation,
+    ) -> Result<(), PermissionDenied>;
+}
+
+/// A policy which accepts every request.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IpsisPolicyAllowAll;
+
+impl IpsisPolicy for IpsisPolicyAllowAll {
+    fn authorize(
+        &self,
+        _account: &AccountRef,
+        _operation: IpsisOperation,
+    ) -> Result<(), PermissionDenied> {
+        Ok(())
+    }
+}
+
+/// A policy which is composed of allow/deny lists per operation.
+///
+/// The rules are evaluated in order:
+/// 1. The admin accounts are allowed to do everything.
+/// 2. The accounts in the deny list of the operation are denied.
+/// 3. If the allow list of the operation is given, the other accounts are denied.
+/// 4. The read-only accounts are denied to write.
+/// 5. If `admin_only_delete` is set, the non-admin accounts are denied to delete.
+/// 6. The non-admin accounts are denied to do the administrative operations.
+#[derive(Clone, Debug, Default)]
+pub struct IpsisPolicyRules {
+    pub allow: HashMap<IpsisOperation, HashSet<AccountRef>>,
+    pub deny: HashMap<IpsisOperation, HashSet<AccountRef>>,
+    pub read_only: HashSet<AccountRef>,
+    pub admins: HashSet<AccountRef>,
+    pub admin_only_delete: bool,
+}
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisPolicyRules {
+    type GenesisArgs = Self;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let mut rules = Self {
+            read_only: infer_accounts("ipsis_policy_read_only")?.unwrap_or_default(),
+            admins: infer_accounts("ipsis_policy_admins")?.unwrap_or_default(),
+            admin_only_delete: infer("ipsis_policy_admin_only_delete").unwrap_or(false),
+            ..Default::default()
+        };
+
+        for operation in IpsisOperation::ALL {
+            let name = operation.as_str();
+            if let Some(accounts) = infer_accounts(&format!("ipsis_policy_allow_{name}"))? {
+                rules.allow.insert(operation, accounts);
+            }
+            if let Some(accounts) = infer_accounts(&format!("ipsis_policy_deny_{name}"))? {
+                rules.deny.insert(operation, accounts);
+            }
+        }
+        Ok(rules)
+    }
+
+    async fn genesis(
+        rules: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Ok(rules)
+    }
+}
+
+impl IpsisPolicy for IpsisPolicyRules {
+    fn authorize(
+        &self,
+        account: &AccountRef,
+        operation: IpsisOperation,
+    ) -> Result<(), PermissionDenied> {
+        let is_admin = self.admins.contains(account);
+        let is_denied = !is_admin
+            && (self
+                .deny
+                .get(&operation)
+                .map(|accounts| accounts.contains(account))
+                .unwrap_or_default()
+                || self
+                    .allow
+                    .get(&operation)
+                    .map(|accounts| !accounts.contains(account))
+                    .unwrap_or_default()
+                || operation.is_write() && self.read_only.contains(account)
+                || operation == IpsisOperation::Delete && self.admin_only_delete
+                || operation.is_admin());
+
+        if is_denied {
+            Err(PermissionDenied {
+                account: *account,
+                operation,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Parses a comma-separated list of the accounts.
+fn infer_accounts(key: &str) -> Result<Option<HashSet<AccountRef>>> {
+    match infer::<_, String>(key) {
+        Ok(accounts) => accounts
+            .split(',')
+            .map(str::trim)
+            .filter(|account| !account.is_empty())
+            .map(|account| account.parse().map_err(Into::into))
+            .collect::<Result<_>>()
+            .map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipis::core::account::Account;
+
+    use super::*;
+
+    #[test]
+    fn test_authorize_revoke() {
+        let account = Account::generate().account_ref();
+        let account_read_only = Account::generate().account_ref();
+
+        let rules = IpsisPolicyRules {
+            read_only: [account_read_only].into_iter().collect(),
+            ..Default::default()
+        };
+        assert!(rules.authorize(&account, IpsisOperation::Revoke).is_ok());
+        assert_eq!(
+            rules.authorize(&account_read_only, IpsisOperation::Revoke),
+            Err(PermissionDenied {
+                account: account_read_only,
+                operation: IpsisOperation::Revoke,
+            }),
+        );
+    }
+}
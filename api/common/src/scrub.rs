@@ -0,0 +1,163 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::{
+        self,
+        sync::{Mutex, MutexGuard},
+    },
+};
+
+/// The bookkeeping of the scrubs, which re-verify the stored objects.
+///
+/// The progress is saved into a checkpoint file after each page of the objects,
+/// so that an interrupted scrub can be resumed.
+pub struct IpsisScrubber {
+    checkpoint_path: PathBuf,
+    quarantine_dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+/// The position of the next page to be scrubbed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScrubCheckpoint {
+    pub namespace: AccountRef,
+    /// The cursor of the next page, or `None` for the first page of the namespace.
+    pub cursor: Option<String>,
+}
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisScrubber {
+    type GenesisArgs = (PathBuf, PathBuf);
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let checkpoint_path = infer("ipsis_client_scrub_checkpoint_path").or_else(|e| {
+            let mut path = ::dirs::home_dir().ok_or(e)?;
+            path.push(".ipsis");
+            path.push("scrub");
+            Result::<_, Error>::Ok(path)
+        })?;
+        let quarantine_dir = infer("ipsis_client_scrub_quarantine_dir").or_else(|e| {
+            let mut dir = ::dirs::home_dir().ok_or(e)?;
+            dir.push(".ipsis");
+            dir.push("quarantine");
+            Result::<_, Error>::Ok(dir)
+        })?;
+        Self::genesis((checkpoint_path, quarantine_dir)).await
+    }
+
+    async fn genesis(
+        (checkpoint_path, quarantine_dir): <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Ok(Self {
+            checkpoint_path,
+            quarantine_dir,
+            lock: Default::default(),
+        })
+    }
+}
+
+impl IpsisScrubber {
+    /// Acquires the right to scrub, as only one scrub can run at once.
+    pub fn lock(&self) -> Result<MutexGuard<'_, ()>> {
+        self.lock
+            .try_lock()
+            .map_err(|_| anyhow!("another scrub is already running"))
+    }
+
+    pub async fn load_checkpoint(&self) -> Result<Option<ScrubCheckpoint>> {
+        let checkpoint = match tokio::fs::read_to_string(&self.checkpoint_path).await {
+            Ok(checkpoint) => checkpoint,
+            Err(_) => return Ok(None),
+        };
+
+        let (namespace, cursor) = match checkpoint.split_once('\n') {
+            Some((namespace, cursor)) => (namespace, cursor),
+            None => bail!("malformed scrub checkpoint: {checkpoint:?}"),
+        };
+        Ok(Some(ScrubCheckpoint {
+            namespace: namespace.parse()?,
+            cursor: Some(cursor)
+                .filter(|cursor| !cursor.is_empty())
+                .map(Into::into),
+        }))
+    }
+
+    pub async fn save_checkpoint(&self, checkpoint: &ScrubCheckpoint) -> Result<()> {
+        // create a directory
+        if let Some(parent) = self.checkpoint_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let data = format!(
+            "{}\n{}",
+            checkpoint.namespace.to_string(),
+            checkpoint.cursor.as_deref().unwrap_or_default(),
+        );
+        let path_temp = self.checkpoint_path.with_extension("tmp");
+        tokio::fs::write(&path_temp, data).await?;
+        tokio::fs::rename(&path_temp, &self.checkpoint_path)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn clear_checkpoint(&self) -> Result<()> {
+        match tokio::fs::remove_file(&self.checkpoint_path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ::std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the file which keeps the quarantined object, creating its directory.
+    pub async fn to_path_quarantine(&self, namespace: &AccountRef, path: &Path) -> Result<PathBuf> {
+        let mut buf = self.quarantine_dir.clone();
+        buf.push(namespace.to_string());
+        tokio::fs::create_dir_all(&buf).await?;
+
+        buf.push(path.value.to_string());
+        Ok(buf)
+    }
+}
+
+/// A limiter of the scrubbed bytes per second.
+pub struct ScrubRateLimiter {
+    rate: u64,
+    begin: Instant,
+    consumed: u64,
+}
+
+impl ScrubRateLimiter {
+    /// Creates a limiter, which is disabled if the rate is `0`.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            begin: Instant::now(),
+            consumed: 0,
+        }
+    }
+
+    /// Waits until the consumed bytes fit in the rate.
+    pub async fn consume(&mut self, len: u64) {
+        if self.rate == 0 {
+            return;
+        }
+        self.consumed += len;
+
+        let expected = Duration::from_secs_f64(self.consumed as f64 / self.rate as f64);
+        let elapsed = self.begin.elapsed();
+        if expected > elapsed {
+            tokio::time::sleep(expected - elapsed).await;
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::{io::SeekFrom, path::PathBuf, time::Duration};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{bail, Error, Result},
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::{
+        self,
+        fs::{File, OpenOptions},
+        io::{AsyncRead, AsyncReadExt, AsyncSeekExt},
+    },
+};
+use ipsis_common::PathRange;
+
+/// A staging area of the interrupted uploads.
+///
+/// Each session is a file which holds the received parts,
+/// so the length of the file is the committed offset of the upload.
+pub struct IpsisUploads {
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisUploads {
+    type GenesisArgs = PathBuf;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let dir = infer("ipsis_client_upload_dir").or_else(|e| {
+            let mut dir = ::dirs::home_dir().ok_or(e)?;
+            dir.push(".ipsis");
+            dir.push("uploads");
+            Result::<_, Error>::Ok(dir)
+        })?;
+        Self::genesis(dir).await
+    }
+
+    async fn genesis(
+        dir: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self { dir })
+    }
+}
+
+impl IpsisUploads {
+    fn to_path_account(&self, account: &AccountRef) -> PathBuf {
+        let mut buf = self.dir.clone();
+        buf.push(account.to_string());
+        buf
+    }
+
+    fn to_path_session(&self, account: &AccountRef, path: &Path) -> PathBuf {
+        let mut buf = self.to_path_account(account);
+        buf.push(format!("{}.part", path.value.to_string()));
+        buf
+    }
+
+    /// Opens an upload session, and returns the committed offset.
+    ///
+    /// If the session already exists, it is resumed.
+    pub async fn begin(&self, account: &AccountRef, path: &Path) -> Result<u64> {
+        // create a directory
+        tokio::fs::create_dir_all(self.to_path_account(account)).await?;
+
+        // open the session
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(self.to_path_session(account, path))
+            .await?;
+
+        let offset = file.metadata().await?.len();
+        if offset > path.len {
+            // the session is broken, so restart it
+            file.set_len(0).await?;
+            Ok(0)
+        } else {
+            Ok(offset)
+        }
+    }
+
+    /// Writes a part of the upload.
+    ///
+    /// The offset should not exceed the committed offset.
+    /// If it precedes, the following data are discarded and overwritten.
+    pub async fn write_part<R>(
+        &self,
+        account: &AccountRef,
+        range: &PathRange,
+        data: R,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        range.validate()?;
+
+        // open the session
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .open(self.to_path_session(account, &range.path))
+            .await
+        {
+            Ok(file) => file,
+            Err(_) => bail!(
+                "the upload session is not found: {}",
+                range.path.value.to_string()
+            ),
+        };
+
+        // validate the offset
+        let offset = file.metadata().await?.len();
+        if range.offset > offset {
+            bail!(
+                "the offset {} exceeds the committed offset {offset}",
+                range.offset,
+            );
+        }
+
+        // write the part
+        file.set_len(range.offset).await?;
+        file.seek(SeekFrom::End(0)).await?;
+        let len = tokio::io::copy(&mut data.take(range.len), &mut file).await?;
+        file.sync_data().await?;
+
+        if len == range.len {
+            Ok(())
+        } else {
+            bail!("the part is truncated: {len}/{}", range.len)
+        }
+    }
+
+    /// Opens the data of the completed upload.
+    pub async fn open_completed(&self, account: &AccountRef, path: &Path) -> Result<File> {
+        let file = match File::open(self.to_path_session(account, path)).await {
+            Ok(file) => file,
+            Err(_) => bail!(
+                "the upload session is not found: {}",
+                path.value.to_string()
+            ),
+        };
+
+        let offset = file.metadata().await?.len();
+        if offset == path.len {
+            Ok(file)
+        } else {
+            bail!("the upload is incomplete: {offset}/{}", path.len)
+        }
+    }
+
+    /// Closes the upload session, discarding the received parts.
+    pub async fn remove(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        tokio::fs::remove_file(self.to_path_session(account, path))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Closes the sessions which have not been updated for the given duration.
+    ///
+    /// Returns the number of the closed sessions.
+    pub async fn remove_stale(&self, ttl: Duration) -> Result<usize> {
+        let mut num_removed = 0;
+
+        let mut accounts = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(account) = accounts.next_entry().await? {
+            if !account.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut sessions = tokio::fs::read_dir(account.path()).await?;
+            while let Some(session) = sessions.next_entry().await? {
+                let is_stale = session
+                    .metadata()
+                    .await?
+                    .modified()?
+                    .elapsed()
+                    .map(|elapsed| elapsed >= ttl)
+                    .unwrap_or_default();
+                if is_stale {
+                    tokio::fs::remove_file(session.path()).await?;
+                    num_removed += 1;
+                }
+            }
+        }
+        Ok(num_removed)
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ipis::{
+    core::value::hash::Hasher,
+    path::Path,
+    tokio::io::{AsyncRead, ReadBuf},
+};
+use ipsis_common::chunk::ManifestHasher;
+
+/// An error which is raised when the received data do not match the requested path.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityError {
+    pub expected: Path,
+    pub actual: Path,
+}
+
+impl ::core::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        write!(
+            f,
+            "integrity error: expected {} ({} bytes), but received {} ({} bytes)",
+            self.expected.value.to_string(),
+            self.expected.len,
+            self.actual.value.to_string(),
+            self.actual.len,
+        )
+    }
+}
+
+impl ::std::error::Error for IntegrityError {}
+
+impl IntegrityError {
+    /// Finds the integrity error from an I/O error, which is raised by [`VerifyingReader`].
+    pub fn from_io_error(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+/// A reader which hashes the data while streaming, and fails at the end if they do not match the path.
+///
+/// The stream should begin with a `u64` length header, which is passed through as it is.
+/// As the path of a chunked object is the hash of its manifest,
+/// the manifest is also rebuilt from the data and compared.
+pub struct VerifyingReader<R> {
+    reader: R,
+    state: Option<VerifyingState>,
+}
+
+struct VerifyingState {
+    expected: Path,
+    header_len: usize,
+    hasher: Hasher,
+    manifest: ManifestHasher,
+}
+
+impl<R> VerifyingReader<R> {
+    pub fn new(reader: R, expected: Path) -> Self {
+        Self {
+            reader,
+            state: Some(VerifyingState {
+                expected,
+                header_len: HEADER_LEN,
+                hasher: Hasher::default(),
+                manifest: ManifestHasher::default(),
+            }),
+        }
+    }
+
+    /// Passes the data through without verification.
+    pub fn unverified(reader: R) -> Self {
+        Self {
+            reader,
+            state: None,
+        }
+    }
+}
+
+impl VerifyingState {
+    fn update(&mut self, mut data: &[u8]) {
+        // skip the header
+        let header_len = self.header_len.min(data.len());
+        self.header_len -= header_len;
+        data = &data[header_len..];
+
+        if !data.is_empty() {
+            self.hasher.update(data);
+            self.manifest.update(data);
+        }
+    }
+
+    fn is_completed(&self) -> bool {
+        self.header_len == 0 && self.hasher.len() as u64 >= self.expected.len
+    }
+
+    fn finalize(self) -> Result<(), IntegrityError> {
+        let actual = Path {
+            len: self.hasher.len() as u64,
+            value: self.hasher.finalize(),
+        };
+        if actual == self.expected {
+            return Ok(());
+        }
+
+        // the data may be the content of a chunked object
+        if actual.len == self.expected.len {
+            let manifest = self.manifest.finalize();
+            if matches!(manifest.to_path(), Ok(path) if path == self.expected) {
+                return Ok(());
+            }
+        }
+
+        Err(IntegrityError {
+            expected: self.expected,
+            actual,
+        })
+    }
+}
+
+impl<R> AsyncRead for VerifyingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let capacity = buf.remaining();
+        match Pin::new(&mut self.reader).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let data = &buf.filled()[filled..];
+                let is_eof = data.is_empty() && capacity > 0;
+
+                let is_completed = match &mut self.state {
+                    Some(state) => {
+                        state.update(data);
+                        is_eof || state.is_completed()
+                    }
+                    None => false,
+                };
+
+                // verify the data, as soon as all of them are received
+                if is_completed {
+                    if let Some(Err(e)) = self.state.take().map(VerifyingState::finalize) {
+                        return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+                    }
+                }
+                Poll::Ready(Ok(()))
+            }
+            poll => poll,
+        }
+    }
+}
+
+/// A reader which hashes the data while streaming,
+/// and fails if they end before the expected length.
+///
+/// As the truncated data are raised as an error rather than the end of the stream,
+/// the storages do not commit them.
+pub struct HashingReader<R> {
+    reader: R,
+    expected_len: u64,
+    hasher: Hasher,
+}
+
+impl<R> HashingReader<R> {
+    pub fn new(reader: R, expected_len: u64) -> Self {
+        Self {
+            reader,
+            expected_len,
+            hasher: Hasher::default(),
+        }
+    }
+
+    /// Returns the path of the data which have been read.
+    pub fn finalize(self) -> Path {
+        Path {
+            len: self.hasher.len() as u64,
+            value: self.hasher.finalize(),
+        }
+    }
+}
+
+impl<R> AsyncRead for HashingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let capacity = buf.remaining();
+        match Pin::new(&mut self.reader).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let data = &buf.filled()[filled..];
+                let is_eof = data.is_empty() && capacity > 0;
+
+                if is_eof && (self.hasher.len() as u64) < self.expected_len {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "the data are truncated: {}/{}",
+                            self.hasher.len(),
+                            self.expected_len,
+                        ),
+                    )));
+                }
+                self.hasher.update(data);
+                Poll::Ready(Ok(()))
+            }
+            poll => poll,
+        }
+    }
+}
+
+const HEADER_LEN: usize = ::core::mem::size_of::<u64>();
+
+#[cfg(test)]
+mod tests {
+    use ipis::{
+        core::{account::Account, anyhow::Result, value::hash::Hash},
+        tokio,
+    };
+    use ipsis_api_persistent_common::IpsisPersistentStorage;
+    use ipsis_api_persistent_memory::IpsisPersistentStorageImpl as IpsisMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_truncated_data() -> Result<()> {
+        let storage = IpsisMemory::new(None);
+        let account = Account::generate().account_ref();
+        let data = vec![42; 100];
+        let path = Path {
+            value: Hash::with_bytes(&data),
+            len: data.len() as u64,
+        };
+
+        // the data end before the path
+        let mut reader = HashingReader::new(::std::io::Cursor::new(data[..50].to_vec()), path.len);
+        assert!(storage.put_raw(&account, &path, &mut reader).await.is_err());
+        assert!(!storage.contains(&account, &path).await?);
+
+        let mut reader = HashingReader::new(::std::io::Cursor::new(data), path.len);
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+        assert_eq!(reader.finalize(), path);
+        Ok(())
+    }
+}
@@ -0,0 +1,630 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::SeekFrom,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use filetime::{set_file_mtime, FileTime};
+use fs2::FileExt;
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{bail, Error, Result},
+        value::hash::{Hash, Hasher},
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::{
+        self,
+        fs::File,
+        io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+        sync::Mutex,
+    },
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+
+/// A persistent storage which caches the objects of the inner storage on the local disk.
+///
+/// The objects are populated while being read from the inner storage,
+/// and the least recently used ones are evicted to keep the cache under its capacity.
+/// The recency is restored from the modification times of the files on startup.
+///
+/// The directory is locked exclusively while opened,
+/// so that its capacity is not exceeded by several processes at once.
+pub struct IpsisPersistentStorageImpl<S> {
+    inner: S,
+    dir: PathBuf,
+    capacity: u64,
+    state: Mutex<CacheState>,
+    next_temp: AtomicU64,
+    _lock: ::std::fs::File,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    recency: BTreeMap<u64, CacheKey>,
+    size: u64,
+    clock: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    namespace: Option<AccountRef>,
+    hash: Hash,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct CacheEntry {
+    len: u64,
+    last_used: u64,
+}
+
+#[async_trait]
+impl<'a, S> Infer<'a> for IpsisPersistentStorageImpl<S>
+where
+    Self: Send,
+    S: Infer<'a, GenesisResult = S> + Send,
+    <S as Infer<'a>>::GenesisArgs: Sized,
+{
+    type GenesisArgs = <S as Infer<'a>>::GenesisArgs;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        Self::new(S::try_infer().await?).await
+    }
+
+    async fn genesis(
+        args: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::new(S::genesis(args).await?).await
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S> {
+    /// Wraps the storage, loading the objects which have been cached before.
+    pub async fn new(inner: S) -> Result<Self> {
+        let dir = infer("ipsis_cache_dir").or_else(|e| {
+            let mut dir = ::dirs::home_dir().ok_or(e)?;
+            dir.push(".ipsis");
+            dir.push("cache");
+            Result::<_, Error>::Ok(dir)
+        })?;
+        let capacity = infer("ipsis_cache_capacity").unwrap_or(1 << 30);
+
+        Self::with_dir(inner, dir, capacity).await
+    }
+
+    async fn with_dir(inner: S, dir: PathBuf, capacity: u64) -> Result<Self> {
+        // create a directory
+        tokio::fs::create_dir_all(&dir).await?;
+
+        // lock the directory
+        let lock = ::std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(dir.join(LOCK_FILENAME))?;
+        if lock.try_lock_exclusive().is_err() {
+            bail!("the cache is used by another process: {}", dir.display());
+        }
+
+        let storage = Self {
+            inner,
+            dir,
+            capacity,
+            state: Default::default(),
+            next_temp: Default::default(),
+            _lock: lock,
+        };
+        storage.load().await?;
+        Ok(storage)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the total length of the cached objects.
+    pub async fn size(&self) -> u64 {
+        self.state.lock().await.size
+    }
+
+    async fn load(&self) -> Result<()> {
+        let mut files = vec![];
+
+        let mut namespaces = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(namespace) = namespaces.next_entry().await? {
+            if !namespace.file_type().await?.is_dir() {
+                continue;
+            }
+            let namespace_name = namespace.file_name();
+            let namespace_value = match namespace_name.to_str() {
+                Some(NAMESPACE_GLOBAL) => None,
+                Some(name) => match name.parse() {
+                    Ok(account) => Some(account),
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+
+            let mut entries = tokio::fs::read_dir(namespace.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                // clean up the interrupted populations
+                let name = entry.file_name();
+                let name = match name.to_str() {
+                    Some(name) if name.ends_with(TEMP_EXTENSION) => {
+                        tokio::fs::remove_file(entry.path()).await?;
+                        continue;
+                    }
+                    Some(name) => name,
+                    None => continue,
+                };
+
+                if let Ok(hash) = name.parse() {
+                    let metadata = entry.metadata().await?;
+                    let key = CacheKey {
+                        namespace: namespace_value,
+                        hash,
+                    };
+                    files.push((metadata.modified()?, key, metadata.len()));
+                }
+            }
+        }
+
+        // restore the recency
+        files.sort_unstable_by_key(|(modified, _, _)| *modified);
+        let evicted = {
+            let mut state = self.state.lock().await;
+            let mut evicted = vec![];
+            for (_, key, len) in files {
+                evicted.extend(state.insert(key, len, self.capacity));
+            }
+            evicted
+        };
+        self.remove_files(evicted).await
+    }
+
+    fn to_key(&self, account: &AccountRef, path: &Path) -> CacheKey
+    where
+        S: IpsisPersistentStorage,
+    {
+        CacheKey {
+            namespace: if self.inner.use_account_as_namespace() {
+                Some(*account)
+            } else {
+                None
+            },
+            hash: path.value,
+        }
+    }
+
+    fn to_path_namespace(&self, key: &CacheKey) -> PathBuf {
+        let mut buf = self.dir.clone();
+        match &key.namespace {
+            Some(namespace) => buf.push(namespace.to_string()),
+            None => buf.push(NAMESPACE_GLOBAL),
+        }
+        buf
+    }
+
+    fn to_path_canonical(&self, key: &CacheKey) -> PathBuf {
+        let mut buf = self.to_path_namespace(key);
+        buf.push(key.hash.to_string());
+        buf
+    }
+
+    fn to_path_temp(&self, key: &CacheKey) -> PathBuf {
+        let mut buf = self.to_path_namespace(key);
+        buf.push(format!(
+            "{}.{}{TEMP_EXTENSION}",
+            key.hash.to_string(),
+            self.next_temp.fetch_add(1, Ordering::Relaxed),
+        ));
+        buf
+    }
+
+    /// Opens the cached object, or returns `None` if it is not cached.
+    async fn open(&self, key: &CacheKey) -> Result<Option<File>> {
+        if !self.state.lock().await.touch(key) {
+            return Ok(None);
+        }
+
+        let path = self.to_path_canonical(key);
+        match File::open(&path).await {
+            Ok(file) => {
+                // keep the recency across restarts
+                set_file_mtime(&path, FileTime::now()).ok();
+                Ok(Some(file))
+            }
+            // the file has been removed outside
+            Err(_) => {
+                self.state.lock().await.remove(key);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Checks whether the populated data are the whole object, before committing them.
+    ///
+    /// The data which have been transformed by the outer storages, such as encryption,
+    /// cannot be verified against the path, so they are checked against the stored length.
+    async fn is_populated(&self, account: &AccountRef, path: &Path, len: u64, hash: &Hash) -> bool
+    where
+        S: IpsisPersistentStorage,
+    {
+        if len == path.len {
+            // the native hashes are verified by the inner storage itself
+            self.inner.use_hash_as_native() || hash == &path.value
+        } else {
+            matches!(self.inner.stat(account, path).await, Ok(stat) if stat.path.len == len)
+        }
+    }
+
+    /// Registers the populated file, evicting the least recently used objects.
+    async fn commit(&self, key: &CacheKey, path_temp: &PathBuf, len: u64) -> Result<()> {
+        tokio::fs::rename(path_temp, self.to_path_canonical(key)).await?;
+
+        let evicted = self.state.lock().await.insert(*key, len, self.capacity);
+        self.remove_files(evicted).await
+    }
+
+    async fn invalidate(&self, key: &CacheKey) -> Result<()> {
+        if self.state.lock().await.remove(key) {
+            self.remove_files(vec![*key]).await
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn remove_files(&self, keys: Vec<CacheKey>) -> Result<()> {
+        for key in keys {
+            match tokio::fs::remove_file(self.to_path_canonical(&key)).await {
+                Ok(()) => continue,
+                Err(e) if e.kind() == ::std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CacheState {
+    /// Marks the entry as the most recently used one, and returns whether it exists.
+    fn touch(&mut self, key: &CacheKey) -> bool {
+        let clock = self.tick();
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(clock, *key);
+                entry.last_used = clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Inserts the entry, and returns the least recently used entries which are evicted.
+    fn insert(&mut self, key: CacheKey, len: u64, capacity: u64) -> Vec<CacheKey> {
+        self.remove(&key);
+
+        let clock = self.tick();
+        self.entries.insert(
+            key,
+            CacheEntry {
+                len,
+                last_used: clock,
+            },
+        );
+        self.recency.insert(clock, key);
+        self.size += len;
+
+        let mut evicted = vec![];
+        while self.size > capacity {
+            let key = match self.recency.values().next() {
+                Some(key) => *key,
+                None => break,
+            };
+            self.remove(&key);
+            evicted.push(key);
+        }
+        evicted
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_used);
+                self.size -= entry.len;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+#[async_trait]
+impl<S> IpsisPersistentStorage for IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    fn protocol(&self) -> &'static str {
+        self.inner.protocol()
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        self.inner.use_hash_as_native()
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        self.inner.use_account_as_namespace()
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // serve from the cache
+        let key = self.to_key(account, path);
+        if let Some(mut file) = self.open(&key).await? {
+            tokio::io::copy(&mut file, writer).await?;
+            return Ok(());
+        }
+
+        // begin populating the cache, if possible
+        tokio::fs::create_dir_all(self.to_path_namespace(&key)).await?;
+        let path_temp = self.to_path_temp(&key);
+        let file = File::create(&path_temp).await.ok();
+
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let recv = async move {
+            let mut tx = tx;
+            self.inner.get_raw(account, path, &mut tx).await
+        };
+        let forward = async move {
+            let mut rx = rx;
+            let mut file = file;
+            let mut hasher = Hasher::default();
+            let mut buf = vec![0; CHUNK_SIZE];
+            let mut len = 0;
+            loop {
+                let chunk_len = rx.read(&mut buf).await?;
+                if chunk_len == 0 {
+                    break;
+                }
+                writer.write_all(&buf[..chunk_len]).await?;
+                hasher.update(&buf[..chunk_len]);
+                len += chunk_len as u64;
+
+                // give up populating, without disturbing the transfer
+                if let Some(stream) = &mut file {
+                    if len > self.capacity || stream.write_all(&buf[..chunk_len]).await.is_err() {
+                        file = None;
+                    }
+                }
+            }
+
+            match file {
+                Some(mut file) => {
+                    file.flush().await?;
+                    Result::<_, Error>::Ok(Some((len, hasher.finalize())))
+                }
+                None => Ok(None),
+            }
+        };
+
+        let (recv, forward) = tokio::join!(recv, forward);
+        match recv.and(forward) {
+            Ok(Some((len, hash))) if self.is_populated(account, path, len, &hash).await => {
+                self.commit(&key, &path_temp, len).await
+            }
+            Ok(_) => {
+                tokio::fs::remove_file(&path_temp).await.ok();
+                Ok(())
+            }
+            Err(e) => {
+                tokio::fs::remove_file(&path_temp).await.ok();
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // serve from the cache
+        let key = self.to_key(account, path);
+        if let Some(mut file) = self.open(&key).await? {
+            file.seek(SeekFrom::Start(offset)).await?;
+            tokio::io::copy(&mut file.take(len), writer).await?;
+            return Ok(());
+        }
+
+        // the partial data are not cached
+        self.inner
+            .get_raw_range(account, path, offset, len, writer)
+            .await
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        self.inner.put_raw(account, path, reader).await
+    }
+
+    async fn import_file(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        file: &::std::path::Path,
+    ) -> Result<bool> {
+        self.inner.import_file(account, path, file).await
+    }
+
+    /// The cached objects may have been deleted from the inner storage, so only it is consulted.
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        self.inner.contains(account, path).await
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        self.inner.stat(account, path).await
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        self.invalidate(&self.to_key(account, path)).await?;
+        self.inner.delete(account, path).await
+    }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        self.inner.list(account, query).await
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        self.inner.namespaces().await
+    }
+
+    async fn repair(&self, account: &AccountRef) -> Result<usize> {
+        self.inner.repair(account).await
+    }
+}
+
+const LOCK_FILENAME: &str = ".lock";
+
+/// The directory of the objects which are shared across all accounts.
+const NAMESPACE_GLOBAL: &str = "_";
+
+const TEMP_EXTENSION: &str = ".tmp";
+
+const CHUNK_SIZE: usize = 4_096;
+
+#[cfg(test)]
+mod tests {
+    use ipis::core::{account::Account, value::hash::Hash};
+    use ipsis_api_persistent_memory::IpsisPersistentStorageImpl as IpsisMemory;
+
+    use super::*;
+
+    async fn put(storage: &IpsisMemory, account: &AccountRef, data: &[u8]) -> Result<Path> {
+        let path = Path {
+            value: Hash::with_bytes(data),
+            len: data.len() as u64,
+        };
+        let mut reader = ::std::io::Cursor::new(data.to_vec());
+        assert!(storage.put_raw(account, &path, &mut reader).await?.is_ok());
+        Ok(path)
+    }
+
+    async fn get<S>(storage: &S, account: &AccountRef, path: &Path) -> Result<Vec<u8>>
+    where
+        S: IpsisPersistentStorage,
+    {
+        let mut buf = vec![];
+        storage.get_raw(account, path, &mut buf).await?;
+        Ok(buf)
+    }
+
+    async fn is_cached<S>(
+        storage: &IpsisPersistentStorageImpl<S>,
+        account: &AccountRef,
+        path: &Path,
+    ) -> bool
+    where
+        S: IpsisPersistentStorage,
+    {
+        let key = storage.to_key(account, path);
+        storage.state.lock().await.entries.contains_key(&key)
+    }
+
+    #[tokio::test]
+    async fn test_restore_recency() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let account = Account::generate().account_ref();
+
+        let inner = IpsisMemory::new(None);
+        let path_a = put(&inner, &account, &[1; 16]).await?;
+        let path_b = put(&inner, &account, &[2; 16]).await?;
+
+        // populate the objects, and read the first one again
+        let storage = IpsisPersistentStorageImpl::with_dir(inner, dir.path().into(), 32).await?;
+        get(&storage, &account, &path_a).await?;
+        tokio::time::sleep(::std::time::Duration::from_millis(10)).await;
+        get(&storage, &account, &path_b).await?;
+        tokio::time::sleep(::std::time::Duration::from_millis(10)).await;
+        get(&storage, &account, &path_a).await?;
+        drop(storage);
+
+        // the least recently used object is evicted after a restart
+        let inner = IpsisMemory::new(None);
+        let path_c = put(&inner, &account, &[3; 16]).await?;
+        let storage = IpsisPersistentStorageImpl::with_dir(inner, dir.path().into(), 32).await?;
+        assert_eq!(get(&storage, &account, &path_c).await?, [3; 16]);
+        assert!(is_cached(&storage, &account, &path_a).await);
+        assert!(!is_cached(&storage, &account, &path_b).await);
+        assert_eq!(get(&storage, &account, &path_a).await?, [1; 16]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_contains_only_inner() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let account = Account::generate().account_ref();
+
+        let inner = IpsisMemory::new(None);
+        let path = put(&inner, &account, &[1; 16]).await?;
+
+        // the verified object is populated
+        let storage = IpsisPersistentStorageImpl::with_dir(inner, dir.path().into(), 32).await?;
+        get(&storage, &account, &path).await?;
+        assert!(is_cached(&storage, &account, &path).await);
+
+        // the object has been deleted from the inner storage
+        storage.inner().delete(&account, &path).await?;
+        assert!(!storage.contains(&account, &path).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lock_exclusively() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+
+        let storage =
+            IpsisPersistentStorageImpl::with_dir(IpsisMemory::new(None), dir.path().into(), 32)
+                .await?;
+        assert!(IpsisPersistentStorageImpl::with_dir(
+            IpsisMemory::new(None),
+            dir.path().into(),
+            32
+        )
+        .await
+        .is_err());
+
+        drop(storage);
+        IpsisPersistentStorageImpl::with_dir(IpsisMemory::new(None), dir.path().into(), 32).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::{io::ErrorKind, path::PathBuf, str::FromStr};
+
+use fs2::FileExt;
+use ipis::{
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+        value::hash::Hash,
+    },
+    log::warn,
+    path::Path,
+    tokio::{
+        self,
+        fs::{File, OpenOptions},
+        io::AsyncWriteExt,
+    },
+};
+
+/// A journal file of the records, which is replayed and compacted on open.
+///
+/// Each record is a line, which is written by its `Display` and read by its `FromStr`.
+///
+/// The journal is locked exclusively while opened,
+/// so that it is not replayed and appended by several processes at once.
+pub struct IpsisJournal {
+    path: PathBuf,
+    file: File,
+    _lock: ::std::fs::File,
+}
+
+impl IpsisJournal {
+    /// Opens the journal, and returns the records to be replayed.
+    ///
+    /// The last record may have been torn by a crash while being appended,
+    /// so it is dropped if it is malformed.
+    pub async fn open<R>(path: PathBuf) -> Result<(Self, Vec<R>)>
+    where
+        R: FromStr<Err = Error>,
+    {
+        // create a directory
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // lock the journal
+        let path_lock = path.with_extension("lock");
+        let lock = ::std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&path_lock)?;
+        if lock.try_lock_exclusive().is_err() {
+            bail!("the journal is used by another process: {}", path.display(),);
+        }
+
+        // read the records
+        let data = match tokio::fs::read_to_string(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = vec![];
+        let mut lines = data.split_inclusive('\n').peekable();
+        while let Some(line) = lines.next() {
+            let is_last = lines.peek().is_none();
+
+            // only the last record can be torn, which may be not terminated yet
+            match line.strip_suffix('\n').map(str::parse) {
+                Some(Ok(record)) => records.push(record),
+                Some(Err(e)) if !is_last => {
+                    bail!("malformed journal {}: {line:?}: {e}", path.display())
+                }
+                _ => warn!(
+                    "dropping the torn record of the journal {}: {line:?}",
+                    path.display(),
+                ),
+            }
+        }
+
+        // open the journal
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        Ok((
+            Self {
+                path,
+                file,
+                _lock: lock,
+            },
+            records,
+        ))
+    }
+
+    /// Rewrites the journal with the given records, which should replay into the same state.
+    pub async fn compact<R>(&mut self, records: impl IntoIterator<Item = R>) -> Result<()>
+    where
+        R: ::core::fmt::Display,
+    {
+        let path_compacted = self.path.with_extension("compact");
+        {
+            let mut file = File::create(&path_compacted).await?;
+            for record in records {
+                file.write_all(record.to_string().as_bytes()).await?;
+            }
+            file.sync_all().await?;
+        }
+        tokio::fs::rename(&path_compacted, &self.path).await?;
+
+        // reopen the journal
+        self.file = OpenOptions::new().append(true).open(&self.path).await?;
+        Ok(())
+    }
+
+    /// Appends the record, which is flushed but not synced.
+    pub async fn append<R>(&mut self, record: &R) -> Result<()>
+    where
+        R: ::core::fmt::Display,
+    {
+        self.file.write_all(record.to_string().as_bytes()).await?;
+        self.file.flush().await.map_err(Into::into)
+    }
+
+    /// Syncs the appended records to the disk.
+    pub async fn sync(&self) -> Result<()> {
+        self.file.sync_data().await.map_err(Into::into)
+    }
+}
+
+/// A path of the objects in a namespace, which is recorded as `<namespace> <hash>`.
+///
+/// The namespace `None` is shared across all accounts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct JournalKey {
+    pub namespace: Option<AccountRef>,
+    pub hash: Hash,
+}
+
+impl JournalKey {
+    pub fn new(namespace: Option<&AccountRef>, path: &Path) -> Self {
+        Self {
+            namespace: namespace.copied(),
+            hash: path.value,
+        }
+    }
+}
+
+impl ::core::fmt::Display for JournalKey {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(f, "{}", namespace.to_string())?,
+            None => write!(f, "{NAMESPACE_GLOBAL}")?,
+        }
+        write!(f, " {}", self.hash.to_string())
+    }
+}
+
+/// The whitespace-separated tokens of a record.
+pub struct JournalTokens<'a> {
+    kind: &'static str,
+    line: &'a str,
+    tokens: ::core::str::SplitWhitespace<'a>,
+}
+
+impl<'a> JournalTokens<'a> {
+    pub fn new(kind: &'static str, line: &'a str) -> Self {
+        Self {
+            kind,
+            line,
+            tokens: line.split_whitespace(),
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<&'a str> {
+        self.tokens
+            .next()
+            .ok_or_else(|| anyhow!("malformed {} record: {:?}", self.kind, self.line))
+    }
+
+    pub fn next_key(&mut self) -> Result<JournalKey> {
+        Ok(JournalKey {
+            namespace: match self.next_token()? {
+                NAMESPACE_GLOBAL => None,
+                namespace => Some(namespace.parse()?),
+            },
+            hash: self.next_token()?.parse()?,
+        })
+    }
+
+    /// Returns the number of the tokens which are not read yet.
+    pub fn remaining(&self) -> usize {
+        self.tokens.clone().count()
+    }
+
+    /// Returns the tokens which are not read yet.
+    pub fn into_rest(self) -> ::core::str::SplitWhitespace<'a> {
+        self.tokens
+    }
+}
+
+/// The wildcard of the namespace, which is shared across all accounts.
+const NAMESPACE_GLOBAL: &str = "*";
+
+#[cfg(test)]
+mod tests {
+    use ipis::tokio;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Record(u64);
+
+    impl ::core::fmt::Display for Record {
+        fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+            writeln!(f, "+ {}", self.0)
+        }
+    }
+
+    impl FromStr for Record {
+        type Err = Error;
+
+        fn from_str(line: &str) -> Result<Self, Self::Err> {
+            let mut tokens = JournalTokens::new("test", line);
+            match tokens.next_token()? {
+                "+" => Ok(Self(tokens.next_token()?.parse()?)),
+                op => bail!("unknown test operation: {op:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_compaction() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        let (mut journal, records) = IpsisJournal::open::<Record>(path.clone()).await?;
+        assert!(records.is_empty());
+        journal.append(&Record(1)).await?;
+        journal.append(&Record(2)).await?;
+        journal.compact([Record(2)]).await?;
+        journal.append(&Record(3)).await?;
+        drop(journal);
+
+        let (_, records) = IpsisJournal::open::<Record>(path).await?;
+        assert_eq!(records, [Record(2), Record(3)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lock_exclusively() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        let journal = IpsisJournal::open::<Record>(path.clone()).await?;
+        assert!(IpsisJournal::open::<Record>(path.clone()).await.is_err());
+
+        drop(journal);
+        assert!(IpsisJournal::open::<Record>(path).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drop_torn_record() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        // the number of the last record may be truncated, so it is dropped even if it parses
+        tokio::fs::write(&path, "+ 1\n+ 2\n+ 3").await?;
+        let (_, records) = IpsisJournal::open::<Record>(path.clone()).await?;
+        assert_eq!(records, [Record(1), Record(2)]);
+
+        tokio::fs::write(&path, "+ 1\n+\n").await?;
+        let (_, records) = IpsisJournal::open::<Record>(path).await?;
+        assert_eq!(records, [Record(1)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject_malformed_record() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+
+        tokio::fs::write(&path, "+ 1\n? 2\n+ 3\n").await?;
+        assert!(IpsisJournal::open::<Record>(path).await.is_err());
+        Ok(())
+    }
+}
@@ -1,24 +1,77 @@
+pub extern crate ipsis_common as common;
+
+pub mod journal;
+
 use ipis::{
     async_trait::async_trait,
     core::{account::AccountRef, anyhow::Result},
     path::Path,
     tokio::io::{AsyncRead, AsyncWrite},
 };
+use ipsis_common::{ListPage, ListQuery, Stat};
 
 #[async_trait]
 pub trait IpsisPersistentStorage {
-    const PROTOCOL: &'static str;
-    const USE_HASH_AS_NATIVE: bool;
+    fn protocol(&self) -> &'static str;
+
+    fn use_hash_as_native(&self) -> bool;
+
+    /// Whether the objects are isolated per account.
+    ///
+    /// If `false`, the objects are shared across all accounts and the `account` arguments are ignored.
+    fn use_account_as_namespace(&self) -> bool;
 
     async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
     where
         W: AsyncWrite + Send + Unpin + 'static;
 
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static;
+
     async fn put_raw<R>(&self, account: &AccountRef, path: &Path, reader: &mut R) -> Result<Result<(), Path>>
     where
         R: AsyncRead + Send + Sync + Unpin + 'static;
 
+    /// Stores a local file as an object without streaming it (e.g. by a reflink or a hardlink).
+    ///
+    /// The file should have been already verified to match the path.
+    /// Returns `false` if the storage cannot import files, so that the file should be streamed.
+    async fn import_file(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        file: &::std::path::Path,
+    ) -> Result<bool> {
+        let _ = (account, path, file);
+        Ok(false)
+    }
+
     async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool>;
 
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat>;
+
     async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()>;
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage>;
+
+    /// Returns the accounts which have stored the objects.
+    ///
+    /// Returns nothing if the objects are shared across all accounts.
+    async fn namespaces(&self) -> Result<Vec<AccountRef>>;
+
+    /// Restores the missing redundant copies of the objects of the account.
+    ///
+    /// Returns the number of the restored copies, which is `0` if the objects are not redundant.
+    async fn repair(&self, account: &AccountRef) -> Result<usize> {
+        let _ = account;
+        Ok(0)
+    }
 }
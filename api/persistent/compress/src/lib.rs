@@ -0,0 +1,451 @@
+use std::io::Cursor;
+
+use async_compression::{
+    tokio::bufread::{ZstdDecoder, ZstdEncoder},
+    Level,
+};
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{bail, Error, Result},
+    },
+    env::{infer, Infer},
+    futures::future::try_join_all,
+    path::Path,
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    },
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+
+/// A persistent storage which compresses the objects of the inner storage with `zstd`.
+///
+/// The paths are still defined over the uncompressed data.
+/// The objects which had been stored before wrapping are read as they are.
+pub struct IpsisPersistentStorageImpl<S> {
+    inner: S,
+    level: i32,
+    min_size: u64,
+}
+
+#[async_trait]
+impl<'a, S> Infer<'a> for IpsisPersistentStorageImpl<S>
+where
+    Self: Send,
+    S: Infer<'a, GenesisResult = S> + Send,
+    <S as Infer<'a>>::GenesisArgs: Sized,
+{
+    type GenesisArgs = <S as Infer<'a>>::GenesisArgs;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        Ok(Self::new(S::try_infer().await?))
+    }
+
+    async fn genesis(
+        args: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Ok(Self::new(S::genesis(args).await?))
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            level: infer("ipsis_compress_level").unwrap_or(3),
+            min_size: infer("ipsis_compress_min_size").unwrap_or(4_096),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    /// Reads the header of the stored object, or `None` if it has been stored without compression.
+    async fn load_header(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        stored_len: u64,
+    ) -> Result<Option<Header>> {
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+        self.inner
+            .get_raw_range(
+                account,
+                path,
+                0,
+                stored_len.min(HEADER_LEN as u64),
+                &mut buf,
+            )
+            .await?;
+        Header::parse(&buf)
+    }
+
+    async fn get_decoded<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: Option<u64>,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let recv = async move {
+            let mut tx = tx;
+            self.inner.get_raw(account, path, &mut tx).await
+        };
+        let decode = async move {
+            let mut rx = rx;
+            let mut buf = Vec::with_capacity(HEADER_LEN);
+            (&mut rx)
+                .take(HEADER_LEN as u64)
+                .read_to_end(&mut buf)
+                .await?;
+
+            let mut reader: Box<dyn AsyncRead + Send + Unpin> = match Header::parse(&buf)? {
+                Some(Header {
+                    mode: Mode::Raw, ..
+                }) => Box::new(rx),
+                Some(Header {
+                    mode: Mode::Zstd, ..
+                }) => Box::new(ZstdDecoder::new(BufReader::new(rx))),
+                None => Box::new(Cursor::new(buf).chain(rx)),
+            };
+
+            // skip the bytes before the range
+            tokio::io::copy(&mut (&mut reader).take(offset), &mut tokio::io::sink()).await?;
+
+            // execute data transfer
+            match len {
+                Some(len) => tokio::io::copy(&mut reader.take(len), writer).await?,
+                None => tokio::io::copy(&mut reader, writer).await?,
+            };
+            Result::<_, Error>::Ok(())
+        };
+
+        let (recv, decode) = tokio::join!(recv, decode);
+        decode?;
+        match recv {
+            // the rest of the object is not read once the range has been decoded
+            Err(e) if len.is_some() && is_broken_pipe(&e) => Ok(()),
+            recv => recv,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> IpsisPersistentStorage for IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    fn protocol(&self) -> &'static str {
+        self.inner.protocol()
+    }
+
+    /// The native hashes are defined over the stored data, so such storages are not compressed.
+    fn use_hash_as_native(&self) -> bool {
+        self.inner.use_hash_as_native()
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        self.inner.use_account_as_namespace()
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        if self.inner.use_hash_as_native() {
+            return self.inner.get_raw(account, path, writer).await;
+        }
+
+        self.get_decoded(account, path, 0, None, writer).await
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        if self.inner.use_hash_as_native() {
+            return self
+                .inner
+                .get_raw_range(account, path, offset, len, writer)
+                .await;
+        }
+
+        // load the header
+        let stored_len = self.inner.stat(account, path).await?.path.len;
+        match self.load_header(account, path, stored_len).await? {
+            // the uncompressed data can be sought directly
+            Some(Header {
+                mode: Mode::Raw, ..
+            }) => {
+                self.inner
+                    .get_raw_range(account, path, HEADER_LEN as u64 + offset, len, writer)
+                    .await
+            }
+            Some(Header {
+                mode: Mode::Zstd, ..
+            }) => {
+                self.get_decoded(account, path, offset, Some(len), writer)
+                    .await
+            }
+            None => {
+                self.inner
+                    .get_raw_range(account, path, offset, len, writer)
+                    .await
+            }
+        }
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        if self.inner.use_hash_as_native() {
+            return self.inner.put_raw(account, path, reader).await;
+        }
+
+        // small objects are not worth compressing
+        let header = Header {
+            mode: if path.len >= self.min_size {
+                Mode::Zstd
+            } else {
+                Mode::Raw
+            },
+            len: path.len,
+        };
+
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let encode = async move {
+            let mut tx = tx;
+            tx.write_all(&header.to_bytes()).await?;
+            match header.mode {
+                Mode::Raw => tokio::io::copy(reader, &mut tx).await?,
+                Mode::Zstd => {
+                    let mut reader = ZstdEncoder::with_quality(
+                        BufReader::new(reader),
+                        Level::Precise(self.level),
+                    );
+                    tokio::io::copy(&mut reader, &mut tx).await?
+                }
+            };
+            tx.shutdown().await?;
+            Result::<_, Error>::Ok(())
+        };
+        let send = async move {
+            let mut rx = rx;
+            self.inner.put_raw(account, path, &mut rx).await
+        };
+
+        let (encode, send) = tokio::join!(encode, send);
+        let result = send?;
+        encode?;
+        Ok(result)
+    }
+
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        self.inner.contains(account, path).await
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        let mut stat = self.inner.stat(account, path).await?;
+        if !self.inner.use_hash_as_native() {
+            if let Some(header) = self.load_header(account, path, stat.path.len).await? {
+                stat.path.len = header.len;
+            }
+        }
+        Ok(stat)
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        self.inner.delete(account, path).await
+    }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        let mut page = self.inner.list(account, query).await?;
+        if !self.inner.use_hash_as_native() {
+            let headers = try_join_all(
+                page.paths
+                    .iter()
+                    .map(|path| self.load_header(account, path, path.len)),
+            )
+            .await?;
+
+            for (path, header) in page.paths.iter_mut().zip(headers) {
+                if let Some(header) = header {
+                    path.len = header.len;
+                }
+            }
+        }
+        Ok(page)
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        self.inner.namespaces().await
+    }
+
+    async fn repair(&self, account: &AccountRef) -> Result<usize> {
+        self.inner.repair(account).await
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Header {
+    mode: Mode,
+    /// The length of the uncompressed data.
+    len: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Raw,
+    Zstd,
+}
+
+impl Header {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut buf = [0; HEADER_LEN];
+        buf[..MAGIC.len()].copy_from_slice(&MAGIC);
+        buf[MAGIC.len()] = match self.mode {
+            Mode::Raw => 0,
+            Mode::Zstd => 1,
+        };
+        buf[MAGIC.len() + 1..].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    fn parse(buf: &[u8]) -> Result<Option<Self>> {
+        if buf.len() < HEADER_LEN || buf[..MAGIC.len()] != MAGIC {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            mode: match buf[MAGIC.len()] {
+                0 => Mode::Raw,
+                1 => Mode::Zstd,
+                mode => bail!("unknown compression mode: {mode}"),
+            },
+            len: u64::from_le_bytes(buf[MAGIC.len() + 1..HEADER_LEN].try_into()?),
+        }))
+    }
+}
+
+fn is_broken_pipe(error: &Error) -> bool {
+    error.chain().any(|error| {
+        matches!(
+            error.downcast_ref::<::std::io::Error>(),
+            Some(error) if error.kind() == ::std::io::ErrorKind::BrokenPipe
+        )
+    })
+}
+
+const MAGIC: [u8; 4] = *b"IPSZ";
+
+/// The magic, the mode and the length of the uncompressed data.
+const HEADER_LEN: usize = MAGIC.len() + 1 + ::core::mem::size_of::<u64>();
+
+const CHUNK_SIZE: usize = 4_096;
+
+#[cfg(test)]
+mod tests {
+    use ipis::{
+        core::{account::Account, value::hash::Hash},
+        tokio,
+    };
+    use ipsis_api_persistent_memory::IpsisPersistentStorageImpl as IpsisMemory;
+
+    use super::*;
+
+    async fn get_range(
+        storage: &IpsisPersistentStorageImpl<IpsisMemory>,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        storage
+            .get_raw_range(account, path, offset, len, &mut data)
+            .await?;
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn test_get_range() -> Result<()> {
+        let account = Account::generate().account_ref();
+        let data: Vec<u8> = (0..1 << 16).map(|i| (i % 251) as u8).collect();
+        let path = Path {
+            value: Hash::with_bytes(&data),
+            len: data.len() as u64,
+        };
+
+        // compressed, stored as it is, and stored before wrapping
+        for min_size in [0, u64::MAX] {
+            let storage = IpsisPersistentStorageImpl {
+                inner: IpsisMemory::new(None),
+                level: 3,
+                min_size,
+            };
+            let mut reader = Cursor::new(data.clone());
+            assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+
+            // the range ends long before the object
+            assert_eq!(
+                get_range(&storage, &account, &path, 100, 50).await?,
+                &data[100..150],
+            );
+            assert_eq!(
+                get_range(&storage, &account, &path, 0, path.len).await?,
+                data,
+            );
+        }
+
+        let storage = IpsisPersistentStorageImpl {
+            inner: IpsisMemory::new(None),
+            level: 3,
+            min_size: 0,
+        };
+        let mut reader = Cursor::new(data.clone());
+        assert!(storage
+            .inner
+            .put_raw(&account, &path, &mut reader)
+            .await?
+            .is_ok());
+        assert_eq!(
+            get_range(&storage, &account, &path, 100, 50).await?,
+            &data[100..150],
+        );
+        Ok(())
+    }
+}
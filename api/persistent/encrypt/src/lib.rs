@@ -0,0 +1,535 @@
+use std::io::Cursor;
+
+use chacha20poly1305::{
+    aead::{
+        generic_array::GenericArray,
+        rand_core::RngCore,
+        stream::{StreamBE32, StreamPrimitive},
+        KeyInit, OsRng, Payload,
+    },
+    Key, XChaCha20Poly1305,
+};
+use hkdf::Hkdf;
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+    },
+    env::{infer, Infer},
+    futures::future::try_join_all,
+    path::Path,
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    },
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+use sha2::Sha256;
+
+/// A persistent storage which encrypts the objects of the inner storage with `XChaCha20-Poly1305`.
+///
+/// Each account has its own data key, which is derived from the master key with `HKDF-SHA256`.
+/// The objects are sealed in segments with the `STREAM` construction,
+/// so that they can be streamed and read in ranges without decrypting the whole data.
+/// The objects which had been stored before wrapping are rejected,
+/// unless they are allowed to be read as they are.
+pub struct IpsisPersistentStorageImpl<S> {
+    inner: S,
+    kdf: Hkdf<Sha256>,
+    allow_plaintext: bool,
+}
+
+#[async_trait]
+impl<'a, S> Infer<'a> for IpsisPersistentStorageImpl<S>
+where
+    Self: Send,
+    S: Infer<'a, GenesisResult = S> + Send,
+    <S as Infer<'a>>::GenesisArgs: Sized,
+{
+    type GenesisArgs = <S as Infer<'a>>::GenesisArgs;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        Self::new(S::try_infer().await?)
+    }
+
+    async fn genesis(
+        args: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::new(S::genesis(args).await?)
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S> {
+    /// Wraps the storage, with the master key given as a 32-byte hex string.
+    pub fn new(inner: S) -> Result<Self> {
+        let master_key: String = infer("ipsis_encryption_master_key")?;
+        let mut storage = Self::with_master_key(inner, &::hex::decode(master_key.trim())?)?;
+        storage.set_allow_plaintext(infer("ipsis_encryption_allow_plaintext").unwrap_or(false));
+        Ok(storage)
+    }
+
+    /// Wraps the storage, with the given 32-byte master key.
+    pub fn with_master_key(inner: S, master_key: &[u8]) -> Result<Self> {
+        if master_key.len() != MASTER_KEY_LEN {
+            bail!(
+                "the master key should be {MASTER_KEY_LEN} bytes, but given {}",
+                master_key.len(),
+            );
+        }
+
+        Ok(Self {
+            inner,
+            kdf: Hkdf::new(Some(KDF_SALT), master_key),
+            allow_plaintext: false,
+        })
+    }
+
+    /// Sets whether the objects which have been stored without encryption are read as they are.
+    pub fn set_allow_plaintext(&mut self, allow_plaintext: bool) {
+        self.allow_plaintext = allow_plaintext;
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn to_cipher(&self, account: &AccountRef, header: &Header) -> Result<Cipher> {
+        let mut key = Key::default();
+        self.kdf
+            .expand(account.to_string().as_bytes(), &mut key)
+            .map_err(|_| anyhow!("failed to derive the data key"))?;
+
+        Ok(Cipher {
+            stream: StreamBE32::from_aead(
+                XChaCha20Poly1305::new(&key),
+                GenericArray::from_slice(&header.nonce),
+            ),
+            header: header.to_bytes(),
+            len: header.len,
+        })
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    /// Reads the header of the stored object, or `None` if it has been stored without encryption.
+    async fn load_header(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        stored_len: u64,
+    ) -> Result<Option<Header>> {
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+        self.inner
+            .get_raw_range(
+                account,
+                path,
+                0,
+                stored_len.min(HEADER_LEN as u64),
+                &mut buf,
+            )
+            .await?;
+        Ok(Header::parse(&buf))
+    }
+
+    /// Checks whether the object which has been stored without encryption can be read.
+    fn verify_plaintext(&self, path: &Path) -> Result<()> {
+        if self.allow_plaintext {
+            Ok(())
+        } else {
+            bail!("the object is not encrypted: {}", path.value.to_string())
+        }
+    }
+}
+
+#[async_trait]
+impl<S> IpsisPersistentStorage for IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    fn protocol(&self) -> &'static str {
+        self.inner.protocol()
+    }
+
+    /// The native hashes are defined over the stored data, so such storages are not encrypted.
+    fn use_hash_as_native(&self) -> bool {
+        self.inner.use_hash_as_native()
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        self.inner.use_account_as_namespace()
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        if self.inner.use_hash_as_native() {
+            return self.inner.get_raw(account, path, writer).await;
+        }
+
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let recv = async move {
+            let mut tx = tx;
+            self.inner.get_raw(account, path, &mut tx).await
+        };
+        let decrypt = async move {
+            let mut rx = rx;
+            let mut buf = Vec::with_capacity(HEADER_LEN);
+            (&mut rx)
+                .take(HEADER_LEN as u64)
+                .read_to_end(&mut buf)
+                .await?;
+
+            match Header::parse(&buf) {
+                Some(header) => {
+                    let cipher = self.to_cipher(account, &header)?;
+                    cipher.decrypt(&mut rx, 0, 0, header.len, writer).await
+                }
+                None => {
+                    self.verify_plaintext(path)?;
+                    tokio::io::copy(&mut Cursor::new(buf).chain(rx), writer).await?;
+                    Ok(())
+                }
+            }
+        };
+
+        let (recv, decrypt) = tokio::join!(recv, decrypt);
+        decrypt.and(recv)
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        if self.inner.use_hash_as_native() {
+            return self
+                .inner
+                .get_raw_range(account, path, offset, len, writer)
+                .await;
+        }
+
+        // load the header
+        let stored_len = self.inner.stat(account, path).await?.path.len;
+        let header = match self.load_header(account, path, stored_len).await? {
+            Some(header) => header,
+            None => {
+                self.verify_plaintext(path)?;
+                return self
+                    .inner
+                    .get_raw_range(account, path, offset, len, writer)
+                    .await;
+            }
+        };
+        if offset + len > header.len {
+            bail!(
+                "the range {offset}..{} exceeds the length {}",
+                offset + len,
+                header.len,
+            );
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        // find the segments which cover the range
+        let cipher = self.to_cipher(account, &header)?;
+        let first = offset / SEGMENT_LEN as u64;
+        let last = (offset + len - 1) / SEGMENT_LEN as u64;
+        let stored_offset = HEADER_LEN as u64 + first * SEALED_SEGMENT_LEN as u64;
+        let stored_len = (first..=last)
+            .map(|index| cipher.sealed_len(index))
+            .sum::<u64>();
+
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let recv = async move {
+            let mut tx = tx;
+            self.inner
+                .get_raw_range(account, path, stored_offset, stored_len, &mut tx)
+                .await
+        };
+        let decrypt = async move {
+            let mut rx = rx;
+            cipher
+                .decrypt(
+                    &mut rx,
+                    first,
+                    offset - first * SEGMENT_LEN as u64,
+                    len,
+                    writer,
+                )
+                .await
+        };
+
+        let (recv, decrypt) = tokio::join!(recv, decrypt);
+        decrypt.and(recv)
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        if self.inner.use_hash_as_native() {
+            return self.inner.put_raw(account, path, reader).await;
+        }
+
+        // the nonce is never reused, even if the same data are stored again
+        let mut header = Header {
+            nonce: Default::default(),
+            len: path.len,
+        };
+        OsRng.fill_bytes(&mut header.nonce);
+        let cipher = self.to_cipher(account, &header)?;
+
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let encrypt = async move {
+            let mut tx = tx;
+            tx.write_all(&cipher.header).await?;
+            cipher.encrypt(reader, &mut tx).await?;
+            tx.shutdown().await?;
+            Result::<_, Error>::Ok(())
+        };
+        let send = async move {
+            let mut rx = rx;
+            self.inner.put_raw(account, path, &mut rx).await
+        };
+
+        let (encrypt, send) = tokio::join!(encrypt, send);
+        let result = send?;
+        encrypt?;
+        Ok(result)
+    }
+
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        self.inner.contains(account, path).await
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        let mut stat = self.inner.stat(account, path).await?;
+        if !self.inner.use_hash_as_native() {
+            match self.load_header(account, path, stat.path.len).await? {
+                Some(header) => stat.path.len = header.len,
+                None => self.verify_plaintext(path)?,
+            }
+        }
+        Ok(stat)
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        self.inner.delete(account, path).await
+    }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        let mut page = self.inner.list(account, query).await?;
+        if !self.inner.use_hash_as_native() {
+            let headers = try_join_all(
+                page.paths
+                    .iter()
+                    .map(|path| self.load_header(account, path, path.len)),
+            )
+            .await?;
+
+            for (path, header) in page.paths.iter_mut().zip(headers) {
+                if let Some(header) = header {
+                    path.len = header.len;
+                }
+            }
+        }
+        Ok(page)
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        self.inner.namespaces().await
+    }
+
+    async fn repair(&self, account: &AccountRef) -> Result<usize> {
+        self.inner.repair(account).await
+    }
+}
+
+/// The `STREAM` cipher of an object.
+///
+/// The header is authenticated along with every segment,
+/// so neither the nonce nor the length can be altered.
+struct Cipher {
+    stream: StreamBE32<XChaCha20Poly1305>,
+    header: [u8; HEADER_LEN],
+    len: u64,
+}
+
+impl Cipher {
+    fn num_segments(&self) -> u64 {
+        // an empty object is sealed as an empty last segment
+        ((self.len + SEGMENT_LEN as u64 - 1) / SEGMENT_LEN as u64).max(1)
+    }
+
+    fn plain_len(&self, index: u64) -> u64 {
+        (self.len - (index * SEGMENT_LEN as u64).min(self.len)).min(SEGMENT_LEN as u64)
+    }
+
+    fn sealed_len(&self, index: u64) -> u64 {
+        self.plain_len(index) + TAG_LEN as u64
+    }
+
+    fn position(&self, index: u64) -> Result<(u32, bool)> {
+        Ok((index.try_into()?, index + 1 == self.num_segments()))
+    }
+
+    async fn encrypt<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = Vec::with_capacity(SEGMENT_LEN);
+        for index in 0..self.num_segments() {
+            let plain_len = self.plain_len(index);
+
+            buf.clear();
+            (&mut *reader).take(plain_len).read_to_end(&mut buf).await?;
+            if buf.len() as u64 != plain_len {
+                bail!("the data are truncated at the segment {index}");
+            }
+
+            let (position, last_block) = self.position(index)?;
+            let sealed = self
+                .stream
+                .encrypt(
+                    position,
+                    last_block,
+                    Payload {
+                        msg: &buf,
+                        aad: &self.header,
+                    },
+                )
+                .map_err(|_| anyhow!("failed to encrypt the segment {index}"))?;
+            writer.write_all(&sealed).await?;
+        }
+        Ok(())
+    }
+
+    /// Decrypts the segments from `first`, skipping `skip` bytes and writing `len` bytes.
+    async fn decrypt<R, W>(
+        &self,
+        reader: &mut R,
+        first: u64,
+        mut skip: u64,
+        mut len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = Vec::with_capacity(SEALED_SEGMENT_LEN);
+        let mut index = first;
+        while len > 0 || index == first {
+            let sealed_len = self.sealed_len(index);
+
+            buf.clear();
+            (&mut *reader)
+                .take(sealed_len)
+                .read_to_end(&mut buf)
+                .await?;
+            if buf.len() as u64 != sealed_len {
+                bail!("the encrypted data are truncated at the segment {index}");
+            }
+
+            let (position, last_block) = self.position(index)?;
+            let plain = self
+                .stream
+                .decrypt(
+                    position,
+                    last_block,
+                    Payload {
+                        msg: &buf,
+                        aad: &self.header,
+                    },
+                )
+                .map_err(|_| anyhow!("failed to decrypt the segment {index}"))?;
+
+            let begin = skip.min(plain.len() as u64);
+            let end = (begin + len).min(plain.len() as u64);
+            writer
+                .write_all(&plain[begin as usize..end as usize])
+                .await?;
+
+            skip -= begin;
+            len -= end - begin;
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Header {
+    nonce: [u8; NONCE_LEN],
+    /// The length of the plain data.
+    len: u64,
+}
+
+impl Header {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut buf = [0; HEADER_LEN];
+        buf[..MAGIC.len()].copy_from_slice(&MAGIC);
+        buf[MAGIC.len()..MAGIC.len() + NONCE_LEN].copy_from_slice(&self.nonce);
+        buf[MAGIC.len() + NONCE_LEN..].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN || buf[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            nonce: buf[MAGIC.len()..MAGIC.len() + NONCE_LEN].try_into().ok()?,
+            len: u64::from_le_bytes(buf[MAGIC.len() + NONCE_LEN..HEADER_LEN].try_into().ok()?),
+        })
+    }
+}
+
+const MAGIC: [u8; 4] = *b"IPSE";
+
+/// The nonce prefix of `STREAM`, which leaves 5 bytes of the 24-byte nonce for the counter.
+const NONCE_LEN: usize = 19;
+
+/// The magic, the nonce prefix and the length of the plain data.
+const HEADER_LEN: usize = MAGIC.len() + NONCE_LEN + ::core::mem::size_of::<u64>();
+
+const MASTER_KEY_LEN: usize = 32;
+
+const KDF_SALT: &[u8] = b"ipsis-api-persistent-encrypt";
+
+const SEGMENT_LEN: usize = 64 * 1024;
+
+const TAG_LEN: usize = 16;
+
+const SEALED_SEGMENT_LEN: usize = SEGMENT_LEN + TAG_LEN;
+
+const CHUNK_SIZE: usize = 4_096;
@@ -0,0 +1,802 @@
+use std::str::FromStr;
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+    },
+    env::{infer, Infer},
+    futures::future::{join_all, try_join_all},
+    log::warn,
+    path::Path,
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    },
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+/// A persistent storage which splits the objects into Reed-Solomon shards over the inner storages.
+///
+/// Each object is striped into the data shards and the parity shards,
+/// and each shard is stored on its own inner storage,
+/// so the objects can be rebuilt as long as any data shards' worth of the storages are available.
+/// The objects which had been stored before wrapping cannot be read.
+pub struct IpsisPersistentStorageImpl<S> {
+    shards: Vec<S>,
+    data_shards: usize,
+    parity_shards: usize,
+    write_quorum: usize,
+}
+
+#[async_trait]
+impl<'a, S> Infer<'a> for IpsisPersistentStorageImpl<S>
+where
+    Self: Send,
+    S: Infer<'a, GenesisResult = S> + IpsisPersistentStorage + Send,
+    <S as Infer<'a>>::GenesisArgs: FromStr + Send + Sized,
+{
+    type GenesisArgs = Vec<<S as Infer<'a>>::GenesisArgs>;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        // the genesis arguments of each shard, separated by commas
+        let shards: String = infer("ipsis_erasure_shards")?;
+        let args = shards
+            .split(',')
+            .map(str::trim)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.parse().map_err(|_| anyhow!("malformed shard: {arg:?}")))
+            .collect::<Result<_>>()?;
+        Self::genesis(args).await
+    }
+
+    async fn genesis(
+        args: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::new(try_join_all(args.into_iter().map(S::genesis)).await?)
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S> {
+    /// Wraps the storages, one for each shard.
+    ///
+    /// A third of the shards are the parity shards by default,
+    /// and every shard should be stored by default to complete the writes.
+    pub fn new(shards: Vec<S>) -> Result<Self>
+    where
+        S: IpsisPersistentStorage,
+    {
+        let num_shards = shards.len();
+        if num_shards < 2 || num_shards > SHARDS_MAX {
+            bail!("the number of the shards should be in 2..={SHARDS_MAX}, but given {num_shards}");
+        }
+        if let Some(shard) = shards.iter().find(|shard| shard.use_hash_as_native()) {
+            bail!(
+                "the shards cannot be stored on the {} storage, which uses the native hashes",
+                shard.protocol(),
+            );
+        }
+        if shards
+            .iter()
+            .any(|shard| shard.use_account_as_namespace() != shards[0].use_account_as_namespace())
+        {
+            bail!("the shards should be stored on the storages with the same namespaces");
+        }
+
+        let parity_shards = infer("ipsis_erasure_parity_shards").unwrap_or((num_shards / 3).max(1));
+        if parity_shards == 0 || parity_shards >= num_shards {
+            bail!("the parity shards should be in 1..{num_shards}, but given {parity_shards}");
+        }
+        let data_shards = num_shards - parity_shards;
+
+        let write_quorum = infer("ipsis_erasure_write_quorum").unwrap_or(num_shards);
+        if write_quorum < data_shards || write_quorum > num_shards {
+            bail!(
+                "the write quorum should be in {data_shards}..={num_shards}, but given {}",
+                write_quorum,
+            );
+        }
+
+        Ok(Self {
+            shards,
+            data_shards,
+            parity_shards,
+            write_quorum,
+        })
+    }
+
+    pub fn shards(&self) -> &[S] {
+        &self.shards
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    pub fn parity_shards(&self) -> usize {
+        self.parity_shards
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    /// Reads the header from any available shard.
+    async fn load_header(&self, account: &AccountRef, path: &Path) -> Result<Header> {
+        let mut errors = vec![];
+        for (index, shard) in self.shards.iter().enumerate() {
+            match load_shard_header(shard, account, path).await {
+                Ok(header) if header.index as usize != index => errors.push(anyhow!(
+                    "the shard {} is stored in the place of the shard {index}",
+                    header.index,
+                )),
+                Ok(header) if header.num_shards() == self.shards.len() => return Ok(header),
+                Ok(header) => bail!(
+                    "the object has been split into {} shards, but {} storages are given",
+                    header.num_shards(),
+                    self.shards.len(),
+                ),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        bail!(
+            "no shard holds the header of {}: {}",
+            path.value.to_string(),
+            fmt_errors(&errors),
+        )
+    }
+
+    /// Reads the stripes which cover the range, rebuilding the missing data shards.
+    async fn get_decoded<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        header: Header,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        if offset + len > header.len {
+            bail!(
+                "the range {offset}..{} exceeds the length {}",
+                offset + len,
+                header.len,
+            );
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        // find the stripes which cover the range
+        let block_len = header.block_len as u64;
+        let stripe_len = header.stripe_len();
+        let first = offset / stripe_len;
+        let last = (offset + len - 1) / stripe_len;
+        let stored_offset = first * block_len;
+        let stored_len = (last - first + 1) * block_len;
+
+        // create the channels
+        let (txs, rxs): (Vec<_>, Vec<_>) = self
+            .shards
+            .iter()
+            .map(|_| tokio::io::duplex(CHUNK_SIZE))
+            .unzip();
+
+        // the missing shards are tolerated while decoding
+        let recv = join_all(self.shards.iter().zip(txs).enumerate().map(
+            |(index, (shard, tx))| async move {
+                let mut tx = tx;
+
+                // the misplaced or stale shards are treated as missing
+                let expected = Header {
+                    index: index as u8,
+                    ..header
+                };
+                let header = load_shard_header(shard, account, path).await?;
+                if header != expected {
+                    bail!(
+                        "the shard {index} of {} mismatches its header: {header:?}",
+                        path.value.to_string(),
+                    );
+                }
+
+                shard
+                    .get_raw_range(account, path, stored_offset, stored_len, &mut tx)
+                    .await
+            },
+        ));
+        let decode = async move {
+            let data_shards = header.data_shards as usize;
+            let codec = ReedSolomon::new(data_shards, header.parity_shards as usize)?;
+            let mut rxs: Vec<Option<DuplexStream>> = rxs.into_iter().map(Some).collect();
+            let mut skip = offset - first * stripe_len;
+            let mut remaining = len;
+
+            for _ in first..=last {
+                // read a block from each shard
+                let mut blocks = Vec::with_capacity(rxs.len());
+                for rx in &mut rxs {
+                    let block = match rx {
+                        Some(stream) => read_block(stream, block_len).await,
+                        None => None,
+                    };
+                    if block.is_none() {
+                        *rx = None;
+                    }
+                    blocks.push(block);
+                }
+
+                // rebuild the missing data blocks
+                let num_available = blocks.iter().filter(|block| block.is_some()).count();
+                if num_available < data_shards {
+                    bail!(
+                        "only {num_available} of {} shards are available, but {} are required",
+                        header.num_shards(),
+                        header.data_shards,
+                    );
+                }
+                if blocks[..data_shards].iter().any(Option::is_none) {
+                    codec.reconstruct_data(&mut blocks)?;
+                }
+
+                // execute data transfer
+                for block in blocks.into_iter().take(data_shards).flatten() {
+                    let begin = skip.min(block_len);
+                    let end = (begin + remaining).min(block_len);
+                    writer
+                        .write_all(&block[begin as usize..end as usize])
+                        .await?;
+
+                    skip -= begin;
+                    remaining -= end - begin;
+                }
+            }
+            Result::<_, Error>::Ok(())
+        };
+
+        let (recv, decode) = tokio::join!(recv, decode);
+        decode.map_err(|e| {
+            let errors: Vec<_> = recv.into_iter().filter_map(Result::err).collect();
+            if errors.is_empty() {
+                e
+            } else {
+                anyhow!("{e}: {}", fmt_errors(&errors))
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl<S> IpsisPersistentStorage for IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    fn protocol(&self) -> &'static str {
+        self.shards[0].protocol()
+    }
+
+    /// The native hashes are defined over the stored data, so they cannot be used for the shards.
+    fn use_hash_as_native(&self) -> bool {
+        false
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        self.shards[0].use_account_as_namespace()
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let header = self.load_header(account, path).await?;
+        self.get_decoded(account, path, header, 0, header.len, writer)
+            .await
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let header = self.load_header(account, path).await?;
+        self.get_decoded(account, path, header, offset, len, writer)
+            .await
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // small objects are split into smaller blocks
+        //
+        // the length of the path is only a hint,
+        // as the data may have been encrypted or compressed by the outer storages
+        let data_shards = self.data_shards as u64;
+        let mut header = Header {
+            data_shards: self.data_shards.try_into()?,
+            parity_shards: self.parity_shards.try_into()?,
+            index: 0,
+            block_len: ((path.len + data_shards - 1) / data_shards).clamp(1, BLOCK_LEN_MAX) as u32,
+            len: 0,
+        };
+        let block_len = header.block_len as usize;
+        let stripe_len = header.stripe_len();
+
+        // create the channels
+        let (txs, rxs): (Vec<_>, Vec<_>) = self
+            .shards
+            .iter()
+            .map(|_| tokio::io::duplex(CHUNK_SIZE))
+            .unzip();
+
+        let encode = async move {
+            let codec = ReedSolomon::new(self.data_shards, self.parity_shards)?;
+            let mut txs: Vec<Option<DuplexStream>> = txs.into_iter().map(Some).collect();
+
+            // the failed shards are left behind
+            async fn send_block(tx: &mut Option<DuplexStream>, data: &[u8]) {
+                if let Some(stream) = tx {
+                    if stream.write_all(data).await.is_err() {
+                        *tx = None;
+                    }
+                }
+            }
+
+            let mut buf = Vec::with_capacity(stripe_len as usize);
+            loop {
+                // read a stripe, until the end of the data
+                buf.clear();
+                (&mut *reader)
+                    .take(stripe_len)
+                    .read_to_end(&mut buf)
+                    .await?;
+                if buf.is_empty() {
+                    break;
+                }
+                header.len += buf.len() as u64;
+                buf.resize(stripe_len as usize, 0);
+
+                // encode the parity blocks
+                let mut blocks: Vec<_> = buf
+                    .chunks(block_len)
+                    .map(<[u8]>::to_vec)
+                    .chain((0..self.parity_shards).map(|_| vec![0; block_len]))
+                    .collect();
+                codec.encode(&mut blocks)?;
+
+                for (tx, block) in txs.iter_mut().zip(&blocks) {
+                    send_block(tx, block).await;
+                }
+                if txs.iter().all(Option::is_none) {
+                    break;
+                }
+            }
+
+            // the header follows the blocks, as the length of the data is known at the end
+            for (index, tx) in txs.iter_mut().enumerate() {
+                let header = Header {
+                    index: index as u8,
+                    ..header
+                };
+                send_block(tx, &header.to_bytes()).await;
+            }
+            Result::<_, Error>::Ok(())
+        };
+        let send = join_all(self.shards.iter().zip(rxs).map(|(shard, rx)| async move {
+            let mut rx = rx;
+            shard.put_raw(account, path, &mut rx).await
+        }));
+
+        let (encode, send) = tokio::join!(encode, send);
+        if let Err(e) = encode {
+            // revert the shards, which may have been stored without their headers
+            for (shard, result) in self.shards.iter().zip(send) {
+                if let Ok(Ok(())) = result {
+                    shard.delete(account, path).await.ok();
+                }
+            }
+            return Err(e);
+        }
+
+        let mut num_stored = 0;
+        let mut errors = vec![];
+        for result in send {
+            match result {
+                Ok(Ok(())) => num_stored += 1,
+                Ok(Err(path)) => return Ok(Err(path)),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if num_stored < self.write_quorum {
+            bail!(
+                "the write quorum is not reached: {num_stored}/{}: {}",
+                self.write_quorum,
+                fmt_errors(&errors),
+            );
+        }
+        if !errors.is_empty() {
+            warn!(
+                "{} of {} shards failed to store {}: {}",
+                errors.len(),
+                self.shards.len(),
+                path.value.to_string(),
+                fmt_errors(&errors),
+            );
+        }
+        Ok(Ok(()))
+    }
+
+    /// Returns whether enough shards are stored to rebuild the object.
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        let mut num_stored = 0;
+        let mut errors = vec![];
+        for shard in &self.shards {
+            match shard.contains(account, path).await {
+                Ok(true) => num_stored += 1,
+                Ok(false) => continue,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.len() == self.shards.len() {
+            bail!("no shard is available: {}", fmt_errors(&errors))
+        } else {
+            Ok(num_stored >= self.data_shards)
+        }
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        let header = self.load_header(account, path).await?;
+
+        let mut errors = vec![];
+        for shard in &self.shards {
+            match shard.stat(account, path).await {
+                Ok(mut stat) => {
+                    stat.path.len = header.len;
+                    return Ok(stat);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        bail!(
+            "no shard could stat {}: {}",
+            path.value.to_string(),
+            fmt_errors(&errors),
+        )
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        let mut errors = vec![];
+        for shard in &self.shards {
+            let result = match shard.contains(account, path).await {
+                Ok(true) => shard.delete(account, path).await,
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            bail!(
+                "failed to delete {} from {} shards: {}",
+                path.value.to_string(),
+                errors.len(),
+                fmt_errors(&errors),
+            )
+        }
+    }
+
+    /// Lists the objects of the first available shard,
+    /// as the cursors cannot be shared across the shards.
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        let mut errors = vec![];
+        for shard in &self.shards {
+            let mut page = match shard.list(account, query).await {
+                Ok(page) => page,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+
+            let headers = join_all(
+                page.paths
+                    .iter()
+                    .map(|path| self.load_header(account, path)),
+            )
+            .await;
+            for (path, header) in page.paths.iter_mut().zip(headers) {
+                // the unreadable objects are kept, so that they can be found by the scrubber
+                match header {
+                    Ok(header) => path.len = header.len,
+                    Err(e) => warn!(
+                        "failed to load the header of {}: {e}",
+                        path.value.to_string(),
+                    ),
+                }
+            }
+            return Ok(page);
+        }
+        bail!("no shard could list: {}", fmt_errors(&errors))
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        let mut errors = vec![];
+        for shard in &self.shards {
+            match shard.namespaces().await {
+                Ok(namespaces) => return Ok(namespaces),
+                Err(e) => errors.push(e),
+            }
+        }
+        bail!("no shard could list: {}", fmt_errors(&errors))
+    }
+}
+
+async fn load_shard_header<S>(shard: &S, account: &AccountRef, path: &Path) -> Result<Header>
+where
+    S: IpsisPersistentStorage,
+{
+    // the header follows the blocks
+    let stored_len = shard.stat(account, path).await?.path.len;
+    if stored_len < HEADER_LEN as u64 {
+        bail!("the object is not a shard");
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_LEN);
+    shard
+        .get_raw_range(
+            account,
+            path,
+            stored_len - HEADER_LEN as u64,
+            HEADER_LEN as u64,
+            &mut buf,
+        )
+        .await?;
+    let header = Header::parse(&buf)?;
+
+    if header.stored_len() != stored_len {
+        bail!(
+            "the shard should be {} bytes, but {stored_len} bytes are stored",
+            header.stored_len(),
+        );
+    }
+    Ok(header)
+}
+
+/// Reads a block, or `None` if the shard is broken.
+async fn read_block(stream: &mut DuplexStream, block_len: u64) -> Option<Vec<u8>> {
+    let mut buf = Vec::with_capacity(block_len as usize);
+    stream.take(block_len).read_to_end(&mut buf).await.ok()?;
+    if buf.len() as u64 == block_len {
+        Some(buf)
+    } else {
+        None
+    }
+}
+
+fn fmt_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Header {
+    data_shards: u8,
+    parity_shards: u8,
+    /// The index of the shard.
+    index: u8,
+    /// The length of the blocks, which are the parts of the shards in each stripe.
+    block_len: u32,
+    /// The length of the whole data, which have been received from the outer storage.
+    len: u64,
+}
+
+impl Header {
+    fn num_shards(&self) -> usize {
+        self.data_shards as usize + self.parity_shards as usize
+    }
+
+    fn stripe_len(&self) -> u64 {
+        self.data_shards as u64 * self.block_len as u64
+    }
+
+    /// The length of each shard, including the header.
+    fn stored_len(&self) -> u64 {
+        let num_stripes = (self.len + self.stripe_len() - 1) / self.stripe_len();
+        num_stripes * self.block_len as u64 + HEADER_LEN as u64
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut buf = [0; HEADER_LEN];
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4] = self.data_shards;
+        buf[5] = self.parity_shards;
+        buf[6] = self.index;
+        buf[7..11].copy_from_slice(&self.block_len.to_le_bytes());
+        buf[11..].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    fn parse(buf: &[u8]) -> Result<Self> {
+        if buf.len() < HEADER_LEN || buf[..4] != MAGIC {
+            bail!("the object is not a shard");
+        }
+
+        Ok(Self {
+            data_shards: buf[4],
+            parity_shards: buf[5],
+            index: buf[6],
+            block_len: u32::from_le_bytes(buf[7..11].try_into()?),
+            len: u64::from_le_bytes(buf[11..HEADER_LEN].try_into()?),
+        })
+    }
+}
+
+const MAGIC: [u8; 4] = *b"IPSR";
+
+/// The magic, the numbers of the shards, the index of the shard,
+/// the length of the blocks and the length of the data.
+const HEADER_LEN: usize =
+    MAGIC.len() + 3 + ::core::mem::size_of::<u32>() + ::core::mem::size_of::<u64>();
+
+const BLOCK_LEN_MAX: u64 = 64 * 1024;
+
+/// The maximum number of the shards, which is limited by `GF(2^8)` and the index in the header.
+const SHARDS_MAX: usize = 255;
+
+const CHUNK_SIZE: usize = 4_096;
+
+#[cfg(test)]
+mod tests {
+    use ipis::core::{account::Account, value::hash::Hash};
+    use ipsis_api_persistent_compress::IpsisPersistentStorageImpl as IpsisCompress;
+    use ipsis_api_persistent_encrypt::IpsisPersistentStorageImpl as IpsisEncrypt;
+    use ipsis_api_persistent_memory::{
+        IpsisMemoryOperation, IpsisPersistentStorageImpl as IpsisMemory,
+    };
+
+    use super::*;
+
+    fn storage() -> Result<IpsisPersistentStorageImpl<IpsisMemory>> {
+        IpsisPersistentStorageImpl::new(vec![
+            IpsisMemory::new(None),
+            IpsisMemory::new(None),
+            IpsisMemory::new(None),
+        ])
+    }
+
+    async fn put<S>(storage: &S, account: &AccountRef, data: &[u8]) -> Result<Path>
+    where
+        S: IpsisPersistentStorage,
+    {
+        let path = Path {
+            value: Hash::with_bytes(data),
+            len: data.len() as u64,
+        };
+        let mut reader = ::std::io::Cursor::new(data.to_vec());
+        assert!(storage.put_raw(account, &path, &mut reader).await?.is_ok());
+        Ok(path)
+    }
+
+    #[tokio::test]
+    async fn test_ignore_misplaced_shard() -> Result<()> {
+        let storage = storage()?;
+        let account = Account::generate().account_ref();
+        let data: Vec<_> = (0..3 * CHUNK_SIZE).map(|i| i as u8).collect();
+        let path = put(&storage, &account, &data).await?;
+
+        // the second shard is restored in the place of the first one
+        let mut shard = vec![];
+        storage.shards()[1]
+            .get_raw(&account, &path, &mut shard)
+            .await?;
+        let mut reader = ::std::io::Cursor::new(shard);
+        storage.shards()[0].delete(&account, &path).await?;
+        assert!(storage.shards()[0]
+            .put_raw(&account, &path, &mut reader)
+            .await?
+            .is_ok());
+
+        let mut buf = vec![];
+        storage.get_raw(&account, &path, &mut buf).await?;
+        assert_eq!(buf, data);
+
+        let mut buf = vec![];
+        storage
+            .get_raw_range(&account, &path, 1, 2 * CHUNK_SIZE as u64, &mut buf)
+            .await?;
+        assert_eq!(buf, data[1..1 + 2 * CHUNK_SIZE]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_unreadable_object() -> Result<()> {
+        let storage = storage()?;
+        let account = Account::generate().account_ref();
+        let path_good = put(&storage, &account, &[1; 100]).await?;
+        let path_bad = put(&storage, &account, &[2; 100]).await?;
+
+        // no shard of the object can be read
+        for shard in storage.shards() {
+            shard.set_fault_hook(Some(Box::new(move |operation, _, path| {
+                if operation == IpsisMemoryOperation::Get && path == Some(&path_bad) {
+                    bail!("the object is unreadable")
+                }
+                Ok(())
+            })));
+        }
+
+        let page = storage.list(&account, &ListQuery::default()).await?;
+        assert_eq!(page.paths.len(), 2);
+        assert!(page.paths.contains(&path_good));
+        assert!(page.paths.iter().any(|path| path.value == path_bad.value));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrap_with_encryption_and_compression() -> Result<()> {
+        let account = Account::generate().account_ref();
+        let data: Vec<_> = (0..5 * CHUNK_SIZE).map(|i| (i % 7) as u8).collect();
+        let path = Path {
+            value: Hash::with_bytes(&data),
+            len: data.len() as u64,
+        };
+
+        // the encrypted data are longer than the path
+        let storage = IpsisEncrypt::with_master_key(storage()?, &[42; 32])?;
+        let mut reader = ::std::io::Cursor::new(data.clone());
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+
+        let mut buf = vec![];
+        storage.get_raw(&account, &path, &mut buf).await?;
+        assert_eq!(buf, data);
+
+        let mut buf = vec![];
+        storage
+            .get_raw_range(&account, &path, 100, 3 * CHUNK_SIZE as u64, &mut buf)
+            .await?;
+        assert_eq!(buf, data[100..100 + 3 * CHUNK_SIZE]);
+        assert_eq!(storage.stat(&account, &path).await?.path.len, path.len);
+
+        // the compressed data are shorter than the path
+        let storage = IpsisCompress::new(storage()?);
+        let mut reader = ::std::io::Cursor::new(data.clone());
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+        assert!(storage.inner().shards()[0].size().await < path.len);
+
+        let mut buf = vec![];
+        storage.get_raw(&account, &path, &mut buf).await?;
+        assert_eq!(buf, data);
+        Ok(())
+    }
+}
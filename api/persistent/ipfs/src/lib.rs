@@ -3,17 +3,26 @@ use http::uri::Scheme;
 use ipfs_api::{IpfsApi, IpfsClient, TryFromUri};
 use ipis::{
     async_trait::async_trait,
-    core::{account::AccountRef, anyhow::Result},
+    core::{
+        account::AccountRef,
+        anyhow::{Error, Result},
+    },
     env::{infer, Infer},
-    futures::TryStreamExt,
+    futures::{future::try_join_all, TryStreamExt},
     path::Path,
     tokio::{
         self,
         io::{AsyncRead, AsyncReadExt, AsyncWrite},
     },
 };
-use ipsis_api_persistent_common::IpsisPersistentStorage;
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
 
+/// A persistent storage on the IPFS node, whose pins are shared across all accounts.
+///
+/// The accounts are not verified here, but by the ledger of the client.
 pub struct IpsisPersistentStorageImpl {
     ipfs: IpfsClient,
 }
@@ -54,15 +63,22 @@ impl IpsisPersistentStorageImpl {
 
 #[async_trait]
 impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
-    const PROTOCOL: &'static str = "ipfs";
-    const USE_HASH_AS_NATIVE: bool = true;
+    fn protocol(&self) -> &'static str {
+        "ipfs"
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        true
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        false
+    }
 
     async fn get_raw<W>(&self, _account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
     where
         W: AsyncWrite + Send + Unpin + 'static,
     {
-        // TODO: verify account
-
         // get canonical path
         let path = *path;
 
@@ -87,6 +103,35 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
             .map_err(Into::into)
     }
 
+    async fn get_raw_range<W>(
+        &self,
+        _account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // get canonical path
+        let path = *path;
+
+        // external call
+        let mut stream = self
+            .ipfs
+            .cat_range(&path.value.to_string(), offset.try_into()?, len.try_into()?)
+            .map_err(|e| ::std::io::Error::new(::std::io::ErrorKind::Other, e))
+            .into_async_read();
+        let mut stream = stream.compat_mut();
+
+        // execute data transfer
+        tokio::io::copy(&mut stream, writer)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
     async fn put_raw<R>(
         &self,
         _account: &AccountRef,
@@ -96,8 +141,6 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
     where
         R: AsyncRead + Send + Sync + Unpin + 'static,
     {
-        // TODO: verify account
-
         // get canonical path
         let path = *path;
 
@@ -129,8 +172,6 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
     }
 
     async fn contains(&self, _account: &AccountRef, path: &Path) -> Result<bool> {
-        // TODO: verify account
-
         // get canonical path
         let path = *path;
 
@@ -141,9 +182,30 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
         Ok(result.is_ok())
     }
 
-    async fn delete(&self, _account: &AccountRef, path: &Path) -> Result<()> {
-        // TODO: verify account
+    async fn stat(&self, _account: &AccountRef, path: &Path) -> Result<Stat> {
+        // get canonical path
+        let path = *path;
+
+        // external call
+        let stat = self
+            .ipfs
+            .files_stat(&format!("/ipfs/{}", path.value.to_string()))
+            .await?;
+
+        // pack data
+        Ok(Stat {
+            path: Path {
+                value: path.value,
+                len: stat.size,
+            },
+            created_date: None,
+            accessed_date: None,
+            protocol: self.protocol().into(),
+            content_type: None,
+        })
+    }
 
+    async fn delete(&self, _account: &AccountRef, path: &Path) -> Result<()> {
         // get canonical path
         let path = *path;
 
@@ -153,4 +215,44 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
         // pack data
         Ok(())
     }
+
+    async fn list(&self, _account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        // external call
+        let response = self.ipfs.pin_ls(None, Some("recursive")).await?;
+
+        // collect the pins after the cursor
+        let mut keys: Vec<_> = response
+            .keys
+            .into_keys()
+            .filter(|key| !matches!(&query.cursor, Some(cursor) if key <= cursor))
+            .collect();
+        keys.sort_unstable();
+
+        // paginate
+        let limit = query.limit();
+        let next_cursor = if keys.len() > limit {
+            keys.truncate(limit);
+            keys.last().cloned()
+        } else {
+            None
+        };
+
+        // external call
+        let paths = try_join_all(keys.into_iter().map(|key| async move {
+            let stat = self.ipfs.files_stat(&format!("/ipfs/{key}")).await?;
+            Result::<_, Error>::Ok(Path {
+                value: key.parse()?,
+                len: stat.size,
+            })
+        }))
+        .await?;
+
+        // pack data
+        Ok(ListPage { paths, next_cursor })
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        // the pins are shared across all accounts
+        Ok(vec![])
+    }
 }
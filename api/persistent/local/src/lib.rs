@@ -1,67 +1,500 @@
-use std::path::PathBuf;
+mod pack;
+
+use std::{
+    io::{Cursor, ErrorKind, SeekFrom},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 use ipis::{
     async_trait::async_trait,
     core::{
         account::AccountRef,
-        anyhow::{Error, Result},
+        anyhow::{bail, Result},
+        chrono::{DateTime, Utc},
+        value::hash::{Hash, Hasher},
     },
     env::{infer, Infer},
+    log::{info, warn},
     path::Path,
     tokio::{
         self,
-        io::{AsyncRead, AsyncWrite},
+        fs::{DirEntry, File},
+        io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite},
     },
 };
-use ipsis_api_persistent_common::IpsisPersistentStorage;
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+
+use self::pack::IpsisPack;
 
+/// A persistent storage which stores the objects as the files in a directory.
+///
+/// The objects are written into the temporary files first,
+/// and then renamed to their canonical paths once completed.
+/// The files of an account are spread over the nested directories named by the hash prefixes,
+/// so that no directory grows too large.
+///
+/// If the packed mode is enabled, the small objects are appended to the shared segment files
+/// instead.
+///
+/// The objects can be spread over several directories (e.g. one per disk).
+/// The new objects are placed on the directory with the most free space,
+/// and the writes are refused once no directory has more free space than the watermark.
+/// The layout and the segments are kept in the first directory.
 pub struct IpsisPersistentStorageImpl {
-    dir: PathBuf,
+    dirs: Vec<PathBuf>,
+    config: IpsisLocalConfig,
+    next_temp: AtomicU64,
+    pack: Option<IpsisPack>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct IpsisLocalConfig {
+    /// Syncs the parent directory after each write, so that the renamed file survives a power loss.
+    pub sync_dir: bool,
+    /// The number of the nested directories of the hash prefixes. `0` stores the files flat.
+    pub fan_out: usize,
+    /// The maximum length of the objects to be packed into the segments. `0` disables the packing.
+    pub pack_threshold: u64,
+    /// The length of each segment, after which the next segment is created.
+    pub segment_size: u64,
+    /// The free space of a directory to be kept, below which the writes are refused.
+    pub min_free: u64,
+    /// Imports the local files by hardlinks if they cannot be reflinked (disabled by default).
+    ///
+    /// The hardlinked files should not be modified afterwards, as they share the stored objects.
+    pub import_hardlink: bool,
+}
+
+impl Default for IpsisLocalConfig {
+    fn default() -> Self {
+        Self {
+            sync_dir: infer("ipsis_client_local_sync_dir").unwrap_or(false),
+            fan_out: infer("ipsis_client_local_fan_out").unwrap_or(2),
+            pack_threshold: infer("ipsis_client_local_pack_threshold").unwrap_or(0),
+            segment_size: infer("ipsis_client_local_segment_size").unwrap_or(64 << 20),
+            min_free: infer("ipsis_client_local_min_free").unwrap_or(64 << 20),
+            import_hardlink: infer("ipsis_client_local_import_hardlink").unwrap_or(false),
+        }
+    }
 }
 
 #[async_trait]
 impl<'a> Infer<'a> for IpsisPersistentStorageImpl {
-    type GenesisArgs = PathBuf;
+    type GenesisArgs = Vec<PathBuf>;
     type GenesisResult = Self;
 
     async fn try_infer() -> Result<Self> {
-        Ok(Self {
-            dir: infer("ipsis_client_local_dir").or_else(|e| {
+        // the directories are separated as `PATH` (e.g. `/mnt/disk0:/mnt/disk1`)
+        let dirs = match infer::<_, String>("ipsis_client_local_dir") {
+            Ok(dirs) => ::std::env::split_paths(&dirs).collect(),
+            Err(e) => {
                 let mut dir = ::dirs::home_dir().ok_or(e)?;
                 dir.push(".ipsis");
-                Result::<_, Error>::Ok(dir)
-            })?,
-        })
+                vec![dir]
+            }
+        };
+
+        Self::genesis(dirs).await
     }
 
     async fn genesis(
-        dir: <Self as Infer<'a>>::GenesisArgs,
+        dirs: <Self as Infer<'a>>::GenesisArgs,
     ) -> Result<<Self as Infer<'a>>::GenesisResult> {
-        Ok(Self { dir })
+        Self::new(dirs, Default::default()).await
     }
 }
 
 impl IpsisPersistentStorageImpl {
-    pub fn to_path_canonical(&self, account: &AccountRef, path: &Path) -> PathBuf {
-        let mut buf = self.dir.clone();
+    /// Opens the directories, removing the temporary files left by the interrupted writes.
+    ///
+    /// If a directory has been stored in another layout (e.g. the flat one),
+    /// the objects are migrated into the configured layout.
+    pub async fn new(dirs: Vec<PathBuf>, config: IpsisLocalConfig) -> Result<Self> {
+        if dirs.is_empty() {
+            bail!("at least one directory should be given");
+        }
+        if config.fan_out > FAN_OUT_MAX {
+            bail!(
+                "the fan-out should be at most {FAN_OUT_MAX}: {}",
+                config.fan_out,
+            );
+        }
+
+        // create the directories, so that their free space can be measured
+        for dir in &dirs {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        // the segments are kept open even if the packing is disabled, to read the packed objects
+        let dir_pack = dirs[0].join(PACK_DIR);
+        let pack = if config.pack_threshold > 0 || tokio::fs::metadata(&dir_pack).await.is_ok() {
+            Some(IpsisPack::open(dir_pack, config.segment_size).await?)
+        } else {
+            None
+        };
+
+        let storage = Self {
+            dirs,
+            config,
+            next_temp: Default::default(),
+            pack,
+        };
+        for disk in 0..storage.dirs.len() {
+            storage.cleanup_temp(disk).await?;
+
+            // migrate the layout
+            if storage.load_layout(disk).await? != Some(config.fan_out) {
+                let num_moved = storage.migrate(disk).await?;
+                if num_moved > 0 {
+                    info!(
+                        "migrated {num_moved} objects into the fan-out of {}: {}",
+                        config.fan_out,
+                        storage.dirs[disk].display(),
+                    );
+                }
+                storage.save_layout(disk).await?;
+            }
+        }
+        Ok(storage)
+    }
+
+    pub fn to_path_account(&self, disk: usize, account: &AccountRef) -> PathBuf {
+        let mut buf = self.dirs[disk].clone();
         buf.push(account.to_string());
-        buf.push(path.value.to_string());
         buf
     }
+
+    pub fn to_path_canonical(&self, disk: usize, account: &AccountRef, path: &Path) -> PathBuf {
+        let name = path.value.to_string();
+        let mut buf = self.to_path_nested(self.to_path_account(disk, account), &name);
+        buf.push(name);
+        buf
+    }
+
+    fn to_path_temp(&self, disk: usize, account: &AccountRef, path: &Path) -> PathBuf {
+        let name = path.value.to_string();
+        let mut buf = self.to_path_nested(self.to_path_account(disk, account), &name);
+        buf.push(format!(
+            "{name}.{}.{}{TEMP_EXTENSION}",
+            ::std::process::id(),
+            self.next_temp.fetch_add(1, Ordering::Relaxed),
+        ));
+        buf
+    }
+
+    /// Appends the directories of the hash prefixes.
+    fn to_path_nested(&self, mut buf: PathBuf, name: &str) -> PathBuf {
+        for level in 0..self.config.fan_out {
+            if let Some(prefix) = name.get(level * FAN_OUT_WIDTH..(level + 1) * FAN_OUT_WIDTH) {
+                buf.push(prefix);
+            }
+        }
+        buf
+    }
+
+    /// Returns the directory which stores the object, if any.
+    async fn find_disk(&self, account: &AccountRef, path: &Path) -> Option<usize> {
+        for disk in 0..self.dirs.len() {
+            let path_canonical = self.to_path_canonical(disk, account, path);
+            if tokio::fs::metadata(path_canonical).await.is_ok() {
+                return Some(disk);
+            }
+        }
+        None
+    }
+
+    /// Returns the file of the object, which is on the first directory if not found.
+    async fn find_file(&self, account: &AccountRef, path: &Path) -> PathBuf {
+        let disk = self.find_disk(account, path).await.unwrap_or_default();
+        self.to_path_canonical(disk, account, path)
+    }
+
+    /// Returns the directory with the most free space among the candidates,
+    /// failing if the write of the given length would exceed the watermark.
+    async fn select_disk(
+        &self,
+        candidates: impl IntoIterator<Item = usize>,
+        len: u64,
+    ) -> Result<usize> {
+        let mut selected = None;
+        for disk in candidates {
+            let dir = self.dirs[disk].clone();
+            let available =
+                tokio::task::spawn_blocking(move || ::fs2::available_space(dir)).await??;
+            if selected.map(|(_, best)| available > best).unwrap_or(true) {
+                selected = Some((disk, available));
+            }
+        }
+
+        match selected {
+            Some((disk, available)) if available >= len.saturating_add(self.config.min_free) => {
+                Ok(disk)
+            }
+            Some((disk, available)) => bail!(
+                "not enough free space: {} (available: {available}, required: {len} + {})",
+                self.dirs[disk].display(),
+                self.config.min_free,
+            ),
+            None => bail!("no directory to be written"),
+        }
+    }
+
+    /// Returns the directories of the accounts, skipping the other files (e.g. the ledger).
+    async fn read_accounts(&self, disk: usize) -> Result<Vec<PathBuf>> {
+        let mut entries = match tokio::fs::read_dir(&self.dirs[disk]).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut accounts = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let is_account = entry
+                .file_name()
+                .to_str()
+                .map(|name| name.parse::<AccountRef>().is_ok())
+                .unwrap_or_default();
+            if is_account && entry.file_type().await?.is_dir() {
+                accounts.push(entry.path());
+            }
+        }
+        Ok(accounts)
+    }
+
+    async fn cleanup_temp(&self, disk: usize) -> Result<()> {
+        for account in self.read_accounts(disk).await? {
+            for entry in read_files_recursive(account).await? {
+                let path = entry.path();
+                if is_stale_temp(&path) {
+                    warn!("removing the stale temporary file: {}", path.display());
+                    match tokio::fs::remove_file(path).await {
+                        Ok(()) => {}
+                        // already committed or removed by its owner
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the objects of the directory which are stored in another layout
+    /// into the configured one.
+    ///
+    /// Returns the number of the moved objects.
+    pub async fn migrate(&self, disk: usize) -> Result<usize> {
+        let mut num_moved = 0;
+        for account in self.read_accounts(disk).await? {
+            for entry in read_files_recursive(account.clone()).await? {
+                let name = match entry.file_name().into_string() {
+                    Ok(name) if name.parse::<Hash>().is_ok() => name,
+                    _ => continue,
+                };
+
+                let path = entry.path();
+                let dir = self.to_path_nested(account.clone(), &name);
+                if path.parent() == Some(dir.as_path()) {
+                    continue;
+                }
+
+                tokio::fs::create_dir_all(&dir).await?;
+                tokio::fs::rename(path, dir.join(name)).await?;
+                num_moved += 1;
+            }
+
+            // remove the directories of the previous layout
+            remove_empty_dirs(account).await?;
+        }
+        Ok(num_moved)
+    }
+
+    async fn load_layout(&self, disk: usize) -> Result<Option<usize>> {
+        match tokio::fs::read_to_string(self.dirs[disk].join(LAYOUT_FILE)).await {
+            Ok(layout) => Ok(Some(layout.trim().parse()?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_layout(&self, disk: usize) -> Result<()> {
+        let path = self.dirs[disk].join(LAYOUT_FILE);
+        let path_temp = path.with_extension("tmp");
+        tokio::fs::write(&path_temp, self.config.fan_out.to_string()).await?;
+        tokio::fs::rename(path_temp, path).await.map_err(Into::into)
+    }
+
+    /// Stores the object as a standalone file.
+    async fn put_file<R>(&self, account: &AccountRef, path: &Path, reader: &mut R) -> Result<()>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        // replace the existing one in place, or place on the directory with the most free space
+        let disk = match self.find_disk(account, path).await {
+            Some(disk) => self.select_disk([disk], path.len).await?,
+            None => self.select_disk(0..self.dirs.len(), path.len).await?,
+        };
+
+        // external call
+        let path_temp = self.create_temp(disk, account, path).await?;
+        if let Err(e) = self.write_temp(&path_temp, reader).await {
+            tokio::fs::remove_file(&path_temp).await.ok();
+            return Err(e);
+        }
+
+        self.commit_temp(disk, account, path, &path_temp).await
+    }
+
+    /// Stores the local file which has been hashed as the object.
+    async fn put_local_file(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        file: &::std::path::Path,
+    ) -> Result<()> {
+        // keep the stored object, whether it is a file or packed
+        if self.contains(account, path).await? {
+            return Ok(());
+        }
+
+        // share the blocks of the file, which is possible only on the same filesystem
+        for disk in 0..self.dirs.len() {
+            let path_temp = self.create_temp(disk, account, path).await?;
+            if self.link_temp(file, &path_temp).await {
+                return self
+                    .commit_import(disk, account, path, file, &path_temp)
+                    .await;
+            }
+        }
+
+        // copy the file, placing on the directory with the most free space
+        let disk = self.select_disk(0..self.dirs.len(), path.len).await?;
+        let path_temp = self.create_temp(disk, account, path).await?;
+        let mut reader = File::open(file).await?;
+        if let Err(e) = self.write_temp(&path_temp, &mut reader).await {
+            tokio::fs::remove_file(&path_temp).await.ok();
+            return Err(e);
+        }
+        self.commit_import(disk, account, path, file, &path_temp)
+            .await
+    }
+
+    /// Shares the blocks of the file by a reflink, or by a hardlink if allowed.
+    async fn link_temp(&self, file: &::std::path::Path, path_temp: &PathBuf) -> bool {
+        let src = file.to_path_buf();
+        let dst = path_temp.clone();
+        let hardlink = self.config.import_hardlink;
+
+        let linked = tokio::task::spawn_blocking(move || {
+            ::reflink_copy::reflink(&src, &dst).is_ok()
+                || (hardlink && ::std::fs::hard_link(&src, &dst).is_ok())
+        })
+        .await
+        .unwrap_or_default();
+        if !linked {
+            tokio::fs::remove_file(path_temp).await.ok();
+        }
+        linked
+    }
+
+    /// Commits the imported file, checking that it has not been modified after being hashed.
+    ///
+    /// The private copy is hashed again, as the file may have been modified until being copied.
+    async fn commit_import(
+        &self,
+        disk: usize,
+        account: &AccountRef,
+        path: &Path,
+        file: &::std::path::Path,
+        path_temp: &PathBuf,
+    ) -> Result<()> {
+        if &hash_file(path_temp).await? != path {
+            tokio::fs::remove_file(path_temp).await.ok();
+            bail!("the file has been modified: {}", file.display());
+        }
+        self.commit_temp(disk, account, path, path_temp).await
+    }
+
+    /// Returns a new temporary file of the object, creating its directory.
+    async fn create_temp(&self, disk: usize, account: &AccountRef, path: &Path) -> Result<PathBuf> {
+        let path_temp = self.to_path_temp(disk, account, path);
+
+        // create a directory
+        let dir = path_temp.ancestors().nth(1).unwrap();
+        tokio::fs::create_dir_all(dir).await?;
+        Ok(path_temp)
+    }
+
+    /// Renames the temporary file to the canonical path of the object.
+    async fn commit_temp(
+        &self,
+        disk: usize,
+        account: &AccountRef,
+        path: &Path,
+        path_temp: &PathBuf,
+    ) -> Result<()> {
+        // get canonical path
+        let path_canonical = self.to_path_canonical(disk, account, path);
+        let dir = path_canonical.ancestors().nth(1).unwrap();
+
+        // commit the file
+        tokio::fs::rename(path_temp, &path_canonical).await?;
+        if self.config.sync_dir {
+            File::open(dir).await?.sync_all().await?;
+        }
+
+        // remove the packed one, which is shadowed by the file
+        if let Some(pack) = &self.pack {
+            pack.remove(account, &path.value).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_temp<R>(&self, path_temp: &PathBuf, reader: &mut R) -> Result<()>
+    where
+        R: AsyncRead + Send + Unpin,
+    {
+        let mut file = File::create(path_temp).await?;
+        tokio::io::copy(reader, &mut file).await?;
+        file.sync_all().await.map_err(Into::into)
+    }
 }
 
 #[async_trait]
 impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
-    const PROTOCOL: &'static str = "local";
-    const USE_HASH_AS_NATIVE: bool = false;
+    fn protocol(&self) -> &'static str {
+        "local"
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        false
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        true
+    }
 
     async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
     where
         W: AsyncWrite + Send + Unpin + 'static,
     {
+        // get packed data
+        if let Some(pack) = &self.pack {
+            if let Some((file, entry)) = pack.open_entry(account, &path.value).await? {
+                return tokio::io::copy(&mut file.take(entry.len), writer)
+                    .await
+                    .map(|_| ())
+                    .map_err(Into::into);
+            }
+        }
+
         // get canonical path
-        let path = *path;
-        let path_canonical = self.to_path_canonical(account, &path);
+        let path_canonical = self.find_file(account, path).await;
 
         // external call
         let mut file = tokio::fs::File::open(path_canonical).await?;
@@ -71,6 +504,41 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
             .map_err(Into::into)
     }
 
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // get packed data
+        if let Some(pack) = &self.pack {
+            if let Some((mut file, entry)) = pack.open_entry(account, &path.value).await? {
+                let len = len.min(entry.len.saturating_sub(offset));
+                file.seek(SeekFrom::Current(offset.try_into()?)).await?;
+                return tokio::io::copy(&mut file.take(len), writer)
+                    .await
+                    .map(|_| ())
+                    .map_err(Into::into);
+            }
+        }
+
+        // get canonical path
+        let path_canonical = self.find_file(account, path).await;
+
+        // external call
+        let mut file = tokio::fs::File::open(path_canonical).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        tokio::io::copy(&mut file.take(len), writer)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
     async fn put_raw<R>(
         &self,
         account: &AccountRef,
@@ -80,34 +548,524 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
     where
         R: AsyncRead + Send + Sync + Unpin + 'static,
     {
-        // get canonical path
-        let path = *path;
-        let path_canonical = self.to_path_canonical(account, &path);
+        // keep the stored object, which may not be clobbered by the unverified data
+        if self.contains(account, path).await? {
+            tokio::io::copy(reader, &mut tokio::io::sink()).await?;
+            return Ok(Ok(()));
+        }
 
-        // create a directory
-        tokio::fs::create_dir_all(path_canonical.ancestors().nth(1).unwrap()).await?;
+        // pack the small objects
+        let threshold = self.config.pack_threshold;
+        if let Some(pack) = self.pack.as_ref().filter(|_| path.len <= threshold) {
+            // receive data, which may be longer than the path (e.g. encrypted)
+            let mut data = vec![];
+            (&mut *reader)
+                .take(threshold + 1)
+                .read_to_end(&mut data)
+                .await?;
 
-        // external call
-        let mut file = tokio::fs::File::create(path_canonical).await?;
-        tokio::io::copy(reader, &mut file)
+            if data.len() as u64 <= threshold {
+                // the segments are kept in the first directory
+                self.select_disk([0], data.len() as u64).await?;
+                pack.put(account, &path.value, &data).await?;
+                return Ok(Ok(()));
+            }
+
+            // too long to be packed
+            let mut reader = Cursor::new(data).chain(reader);
+            return self.put_file(account, path, &mut reader).await.map(Ok);
+        }
+
+        self.put_file(account, path, reader).await.map(Ok)
+    }
+
+    async fn import_file(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        file: &::std::path::Path,
+    ) -> Result<bool> {
+        self.put_local_file(account, path, file)
             .await
-            .map(|_| Ok(()))
-            .map_err(Into::into)
+            .map(|()| true)
     }
 
     async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        // get packed data
+        if let Some(pack) = &self.pack {
+            if pack.get(account, &path.value).await.is_some() {
+                return Ok(true);
+            }
+        }
+
+        // external call
+        Ok(self.find_disk(account, path).await.is_some())
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        // get packed data
+        if let Some(pack) = &self.pack {
+            if let Some(entry) = pack.get(account, &path.value).await {
+                return Ok(Stat {
+                    path: Path {
+                        value: path.value,
+                        len: entry.len,
+                    },
+                    created_date: Some(entry.created_date.into()),
+                    accessed_date: None,
+                    protocol: self.protocol().into(),
+                    content_type: None,
+                });
+            }
+        }
+
         // get canonical path
-        let path = self.to_path_canonical(account, path);
+        let path = *path;
+        let path_canonical = self.find_file(account, &path).await;
 
         // external call
-        Ok(tokio::fs::metadata(path).await.is_ok())
+        let metadata = tokio::fs::metadata(path_canonical).await?;
+
+        // pack data
+        Ok(Stat {
+            path: Path {
+                value: path.value,
+                len: metadata.len(),
+            },
+            created_date: metadata
+                .created()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).into()),
+            accessed_date: metadata
+                .accessed()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).into()),
+            protocol: self.protocol().into(),
+            content_type: None,
+        })
     }
 
     async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        // delete packed data
+        if let Some(pack) = &self.pack {
+            if pack.remove(account, &path.value).await? {
+                return Ok(());
+            }
+        }
+
         // get canonical path
-        let path = self.to_path_canonical(account, path);
+        let path = self.find_file(account, path).await;
 
         // external call
         tokio::fs::remove_file(path).await.map_err(Into::into)
     }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        // get canonical paths
+        // (each directory is on the cursor's path if all its prefixes equal the cursor's ones)
+        let is_on_cursor = query.cursor.is_some();
+        let mut dirs: Vec<_> = (0..self.dirs.len())
+            .map(|disk| (self.to_path_account(disk, account), 0, is_on_cursor))
+            .collect();
+
+        // collect the entries after the cursor
+        let mut names = vec![];
+        while let Some((dir, depth, is_on_cursor)) = dirs.pop() {
+            // external call
+            let mut entries = match tokio::fs::read_dir(dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+
+                if file_type.is_dir() {
+                    if depth >= self.config.fan_out {
+                        continue;
+                    }
+
+                    // skip the prefixes before the cursor, only under the cursor's path
+                    let prefix = match &query.cursor {
+                        Some(cursor) if is_on_cursor => {
+                            cursor.get(depth * FAN_OUT_WIDTH..(depth + 1) * FAN_OUT_WIDTH)
+                        }
+                        _ => None,
+                    };
+                    match prefix {
+                        Some(prefix) if name.as_str() < prefix => {}
+                        Some(prefix) => dirs.push((entry.path(), depth + 1, name == prefix)),
+                        None => dirs.push((entry.path(), depth + 1, false)),
+                    }
+                    continue;
+                }
+                if !file_type.is_file() || depth != self.config.fan_out {
+                    continue;
+                }
+                if matches!(&query.cursor, Some(cursor) if &name <= cursor) {
+                    continue;
+                }
+                if let Ok(hash) = name.parse::<Hash>() {
+                    names.push((name, hash, ListEntry::File(entry)));
+                }
+            }
+        }
+
+        // collect the packed entries after the cursor
+        if let Some(pack) = &self.pack {
+            for (hash, len) in pack.list(account).await {
+                let name = hash.to_string();
+                if !matches!(&query.cursor, Some(cursor) if &name <= cursor) {
+                    names.push((name, hash, ListEntry::Packed(len)));
+                }
+            }
+        }
+        names.sort_unstable_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        names.dedup_by(|(a, _, _), (b, _, _)| a == b);
+
+        // paginate
+        let limit = query.limit();
+        let next_cursor = if names.len() > limit {
+            names.truncate(limit);
+            names.last().map(|(name, _, _)| name.clone())
+        } else {
+            None
+        };
+
+        // pack data
+        let mut paths = Vec::with_capacity(names.len());
+        for (_, value, entry) in names {
+            paths.push(Path {
+                value,
+                len: match entry {
+                    ListEntry::File(entry) => entry.metadata().await?.len(),
+                    ListEntry::Packed(len) => len,
+                },
+            });
+        }
+        Ok(ListPage { paths, next_cursor })
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        let mut namespaces = vec![];
+        for disk in 0..self.dirs.len() {
+            for dir in self.read_accounts(disk).await? {
+                if let Some(Ok(namespace)) = dir
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(str::parse)
+                {
+                    namespaces.push(namespace);
+                }
+            }
+        }
+        if let Some(pack) = &self.pack {
+            namespaces.extend(pack.accounts().await);
+        }
+
+        namespaces.sort_by_cached_key(ToString::to_string);
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+}
+
+enum ListEntry {
+    File(DirEntry),
+    Packed(u64),
+}
+
+/// Returns the files under the directory, including the ones of the nested directories.
+async fn read_files_recursive(dir: PathBuf) -> Result<Vec<DirEntry>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the path which is derived from the data of the file.
+async fn hash_file(path: &::std::path::Path) -> Result<Path> {
+    let mut file = File::open(path).await?;
+    let mut chunk = vec![0; HASH_CHUNK_SIZE];
+    let mut hasher = Hasher::default();
+    loop {
+        let len = file.read(&mut chunk).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&chunk[..len]);
+    }
+
+    Ok(Path {
+        len: hasher.len() as u64,
+        value: hasher.finalize(),
+    })
+}
+
+/// Returns whether the temporary file is left by a process which is no longer running.
+///
+/// The temporary files are named as `<hash>.<pid>.<n>.tmp`.
+fn is_stale_temp(path: &::std::path::Path) -> bool {
+    let stem = match path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_suffix(TEMP_EXTENSION))
+    {
+        Some(stem) => stem,
+        None => return false,
+    };
+
+    let pid = stem
+        .rsplit('.')
+        .nth(1)
+        .and_then(|pid| pid.parse::<u32>().ok());
+    match pid {
+        Some(pid) if pid == ::std::process::id() => false,
+        Some(pid) if cfg!(target_os = "linux") => {
+            let path_proc = ::std::path::Path::new("/proc").join(pid.to_string());
+            !path_proc.exists()
+        }
+        // the owner cannot be checked, so only the old ones are removed
+        _ => path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.elapsed().ok())
+            .map(|age| age > TEMP_MAX_AGE)
+            .unwrap_or_default(),
+    }
+}
+
+/// Removes the empty directories under the directory, keeping the directory itself.
+async fn remove_empty_dirs(dir: PathBuf) -> Result<()> {
+    let mut found = vec![];
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                dirs.push(entry.path());
+                found.push(entry.path());
+            }
+        }
+    }
+
+    // the children are found after their parents, so they are removed first
+    for dir in found.into_iter().rev() {
+        tokio::fs::remove_dir(dir).await.ok();
+    }
+    Ok(())
+}
+
+/// The file which records the fan-out of the stored objects.
+const LAYOUT_FILE: &str = "layout";
+
+const FAN_OUT_MAX: usize = 4;
+
+/// The directory of the segments of the packed objects.
+const PACK_DIR: &str = "packs";
+
+/// The length of the buffer to hash the imported files.
+const HASH_CHUNK_SIZE: usize = 64 << 10;
+
+/// The length of each hash prefix of the nested directories.
+const FAN_OUT_WIDTH: usize = 2;
+
+const TEMP_EXTENSION: &str = ".tmp";
+
+/// The age of the temporary files to be removed, whose owners cannot be checked.
+const TEMP_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[cfg(test)]
+mod tests {
+    use ipis::{core::account::Account, tokio};
+
+    use super::*;
+
+    fn config(pack_threshold: u64) -> IpsisLocalConfig {
+        IpsisLocalConfig {
+            sync_dir: false,
+            fan_out: 2,
+            pack_threshold,
+            segment_size: 1 << 10,
+            min_free: 0,
+            import_hardlink: false,
+        }
+    }
+
+    fn path(data: &[u8]) -> Path {
+        Path {
+            value: Hash::with_bytes(data),
+            len: data.len() as u64,
+        }
+    }
+
+    async fn get(
+        storage: &IpsisPersistentStorageImpl,
+        account: &AccountRef,
+        path: &Path,
+    ) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        storage.get_raw(account, path, &mut data).await?;
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn test_keep_stored_object() -> Result<()> {
+        for pack_threshold in [0, 1 << 8] {
+            let dir = ::tempfile::tempdir()?;
+            let storage =
+                IpsisPersistentStorageImpl::new(vec![dir.path().into()], config(pack_threshold))
+                    .await?;
+            let account = Account::generate().account_ref();
+            let path = path(b"hello");
+
+            let mut reader = &b"hello"[..];
+            assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+
+            // the unverified data is consumed, but not stored
+            let mut reader = &b"world"[..];
+            assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+            assert!(reader.is_empty());
+            assert_eq!(get(&storage, &account, &path).await?, b"hello");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_file() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let storage =
+            IpsisPersistentStorageImpl::new(vec![dir.path().join("store")], config(0)).await?;
+        let account = Account::generate().account_ref();
+        let path = path(b"hello");
+
+        // the file which has been modified after being hashed
+        let file = dir.path().join("file");
+        tokio::fs::write(&file, b"world").await?;
+        assert!(storage.import_file(&account, &path, &file).await.is_err());
+        assert!(!storage.contains(&account, &path).await?);
+
+        tokio::fs::write(&file, b"hello").await?;
+        assert!(storage.import_file(&account, &path, &file).await?);
+        assert_eq!(get(&storage, &account, &path).await?, b"hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_packed_file() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let storage =
+            IpsisPersistentStorageImpl::new(vec![dir.path().join("store")], config(1 << 8)).await?;
+        let account = Account::generate().account_ref();
+        let path = path(b"hello");
+
+        let mut reader = &b"hello"[..];
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+
+        // the packed object is kept, without a standalone copy
+        let file = dir.path().join("file");
+        tokio::fs::write(&file, b"hello").await?;
+        assert!(storage.import_file(&account, &path, &file).await?);
+        assert!(storage.find_disk(&account, &path).await.is_none());
+        assert_eq!(get(&storage, &account, &path).await?, b"hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_pages() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let dirs = vec![dir.path().join("disk0"), dir.path().join("disk1")];
+        // the shorter objects are packed, and the others are spread over the nested directories
+        let storage = IpsisPersistentStorageImpl::new(dirs, config(8)).await?;
+        let account = Account::generate().account_ref();
+
+        let mut expected = vec![];
+        for i in 0..64 {
+            let data = format!("object {i}");
+            let path = path(data.as_bytes());
+            let mut reader = Cursor::new(data.into_bytes());
+            assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+            expected.push(path.value.to_string());
+        }
+        expected.sort();
+
+        let mut found = vec![];
+        let mut query = ListQuery {
+            cursor: None,
+            limit: 5,
+        };
+        loop {
+            let page = storage.list(&account, &query).await?;
+            found.extend(page.paths.iter().map(|path| path.value.to_string()));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(found, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let storage = IpsisPersistentStorageImpl::new(vec![dir.path().into()], config(8)).await?;
+        let (a, b) = (
+            Account::generate().account_ref(),
+            Account::generate().account_ref(),
+        );
+
+        // `a` stores a file, and `b` stores a packed object
+        let mut reader = &b"a long object"[..];
+        let path_a = path(reader);
+        assert!(storage.put_raw(&a, &path_a, &mut reader).await?.is_ok());
+        let mut reader = &b"short"[..];
+        let path_b = path(reader);
+        assert!(storage.put_raw(&b, &path_b, &mut reader).await?.is_ok());
+
+        let mut expected = vec![a, b];
+        expected.sort_by_cached_key(ToString::to_string);
+        assert_eq!(storage.namespaces().await?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_stale_temp() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let storage = IpsisPersistentStorageImpl::new(vec![dir.path().into()], config(0)).await?;
+        let account = Account::generate().account_ref();
+        let path = path(b"hello");
+
+        // the temporary file of a running process
+        let path_alive = storage.create_temp(0, &account, &path).await?;
+        tokio::fs::write(&path_alive, b"hel").await?;
+
+        // the temporary file of a terminated process
+        let path_stale = path_alive.with_file_name(format!(
+            "{}.{}.0{TEMP_EXTENSION}",
+            path.value.to_string(),
+            u32::MAX,
+        ));
+        tokio::fs::write(&path_stale, b"hel").await?;
+
+        storage.cleanup_temp(0).await?;
+        assert!(tokio::fs::metadata(&path_alive).await.is_ok());
+        assert!(tokio::fs::metadata(&path_stale).await.is_err());
+        Ok(())
+    }
 }
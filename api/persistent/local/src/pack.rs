@@ -0,0 +1,671 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{ErrorKind, SeekFrom},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+
+use ipis::{
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+        chrono::{self, DateTime, Utc},
+        value::hash::Hash,
+    },
+    log::{info, warn},
+    tokio::{
+        self,
+        fs::{File, OpenOptions},
+        io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+        sync::{Mutex, Notify},
+    },
+};
+use ipsis_api_persistent_common::journal::{IpsisJournal, JournalTokens};
+
+/// The segment files which pack the small objects together.
+///
+/// The objects are appended to the active segment, and the index maps them to their locations.
+/// Every change of the index is appended to a journal file,
+/// which is replayed and compacted on startup.
+///
+/// The concurrent writes are committed together, sharing the syncs of the segment and the journal.
+/// The segments which are mostly garbage are compacted in the background.
+pub struct IpsisPack {
+    inner: Arc<PackInner>,
+}
+
+struct PackInner {
+    dir: PathBuf,
+    segment_size: u64,
+    state: Mutex<PackState>,
+    /// The journal, whose lock is held by the writer which commits the pending records.
+    journal: Mutex<IpsisJournal>,
+    compaction: Arc<Notify>,
+}
+
+struct PackState {
+    entries: HashMap<PackKey, PackEntry>,
+    segments: BTreeMap<u32, Segment>,
+    /// The segment which the objects are appended to, which is created on the first write.
+    active: Option<(u32, File)>,
+    /// The records which are applied to the entries, but not written to the journal yet.
+    pending: Vec<Record>,
+    /// The number of the records which have been applied.
+    written: u64,
+    /// The number of the records which have been synced to the journal.
+    committed: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct PackKey {
+    account: AccountRef,
+    hash: Hash,
+}
+
+/// The location of a packed object.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PackEntry {
+    pub segment: u32,
+    pub offset: u64,
+    pub len: u64,
+    pub created_date: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Segment {
+    size: u64,
+    /// The total length of the objects which are still referred by the index.
+    live: u64,
+}
+
+impl Drop for IpsisPack {
+    fn drop(&mut self) {
+        // wake up the compaction to be stopped
+        self.inner.compaction.notify_one();
+    }
+}
+
+impl IpsisPack {
+    /// Opens the segments in the directory, reclaiming the space of the deleted objects.
+    pub async fn open(dir: PathBuf, segment_size: u64) -> Result<Self> {
+        tokio::fs::create_dir_all(&dir).await?;
+
+        // find the segments
+        let mut segments: BTreeMap<u32, Segment> = BTreeMap::default();
+        let mut files = tokio::fs::read_dir(&dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            let id = match file.file_name().to_str().and_then(parse_segment_name) {
+                Some(id) => id,
+                None => continue,
+            };
+            let size = file.metadata().await?.len();
+            segments.insert(id, Segment { size, live: 0 });
+        }
+
+        // replay the journal
+        let (mut journal, records) = IpsisJournal::open(dir.join(INDEX_FILE)).await?;
+        let mut entries: HashMap<_, PackEntry> = HashMap::default();
+        for record in records {
+            match record {
+                Record::Add { key, entry } => {
+                    entries.insert(key, entry);
+                }
+                Record::Remove { key } => {
+                    entries.remove(&key);
+                }
+            }
+        }
+        entries.retain(|key, entry| match segments.get_mut(&entry.segment) {
+            Some(segment) if entry.offset + entry.len <= segment.size => {
+                segment.live += entry.len;
+                true
+            }
+            _ => {
+                warn!(
+                    "dropping the packed object which is lost: {}",
+                    key.hash.to_string(),
+                );
+                false
+            }
+        });
+
+        // compact the journal
+        journal
+            .compact(entries.iter().map(|(key, entry)| Record::Add {
+                key: *key,
+                entry: *entry,
+            }))
+            .await?;
+
+        let inner = Arc::new(PackInner {
+            dir,
+            segment_size,
+            state: Mutex::new(PackState {
+                entries,
+                segments,
+                active: None,
+                pending: vec![],
+                written: 0,
+                committed: 0,
+            }),
+            journal: Mutex::new(journal),
+            compaction: Arc::default(),
+        });
+
+        // reclaim the space in the background
+        PackInner::spawn_compaction(&inner);
+        inner.compaction.notify_one();
+        Ok(Self { inner })
+    }
+
+    pub async fn get(&self, account: &AccountRef, hash: &Hash) -> Option<PackEntry> {
+        let key = PackKey::new(account, hash);
+
+        let state = self.inner.state.lock().await;
+        state.entries.get(&key).copied()
+    }
+
+    /// Opens the segment of the object, seeking to the beginning of the object.
+    ///
+    /// The opened file remains readable even if the segment is compacted later.
+    pub async fn open_entry(
+        &self,
+        account: &AccountRef,
+        hash: &Hash,
+    ) -> Result<Option<(File, PackEntry)>> {
+        let key = PackKey::new(account, hash);
+
+        let state = self.inner.state.lock().await;
+        let entry = match state.entries.get(&key) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+
+        let mut file = File::open(self.inner.to_path_segment(entry.segment)).await?;
+        file.seek(SeekFrom::Start(entry.offset)).await?;
+        Ok(Some((file, entry)))
+    }
+
+    /// Returns the hashes and the lengths of the objects of the account.
+    pub async fn list(&self, account: &AccountRef) -> Vec<(Hash, u64)> {
+        let state = self.inner.state.lock().await;
+        state
+            .entries
+            .iter()
+            .filter(|(key, _)| &key.account == account)
+            .map(|(key, entry)| (key.hash, entry.len))
+            .collect()
+    }
+
+    /// Returns the accounts which have packed the objects.
+    pub async fn accounts(&self) -> Vec<AccountRef> {
+        let state = self.inner.state.lock().await;
+        let accounts: HashSet<_> = state.entries.keys().map(|key| key.account).collect();
+        accounts.into_iter().collect()
+    }
+
+    pub async fn put(&self, account: &AccountRef, hash: &Hash, data: &[u8]) -> Result<()> {
+        let key = PackKey::new(account, hash);
+
+        let seq = {
+            let mut state = self.inner.state.lock().await;
+            let (segment, offset) = self.inner.append(&mut state, data).await?;
+
+            let entry = PackEntry {
+                segment,
+                offset,
+                len: data.len() as u64,
+                created_date: Utc::now(),
+            };
+            state.insert(key, entry)
+        };
+        self.inner.commit(seq).await
+    }
+
+    /// Removes the object, and returns `false` if it has not been packed.
+    pub async fn remove(&self, account: &AccountRef, hash: &Hash) -> Result<bool> {
+        let key = PackKey::new(account, hash);
+
+        let (seq, is_compactable) = {
+            let mut state = self.inner.state.lock().await;
+            let entry = match state.entries.remove(&key) {
+                Some(entry) => entry,
+                None => return Ok(false),
+            };
+
+            let segment = state.segments.entry(entry.segment).or_default();
+            segment.live -= entry.len;
+            (
+                state.push(Record::Remove { key }),
+                state.is_compactable(entry.segment),
+            )
+        };
+        self.inner.commit(seq).await?;
+
+        // compact the segment if it is mostly garbage
+        if is_compactable {
+            self.inner.compaction.notify_one();
+        }
+        Ok(true)
+    }
+}
+
+impl PackInner {
+    fn to_path_segment(&self, id: u32) -> PathBuf {
+        self.dir.join(format!("{id:08}{SEGMENT_EXTENSION}"))
+    }
+
+    /// Compacts the segments whenever notified, until the pack is dropped.
+    fn spawn_compaction(inner: &Arc<Self>) {
+        let compaction = inner.compaction.clone();
+        let inner = Arc::downgrade(inner);
+
+        tokio::spawn(async move {
+            loop {
+                compaction.notified().await;
+
+                // the pack is not kept alive while waiting
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => break,
+                };
+                match inner.compact().await {
+                    Ok(0) => {}
+                    Ok(reclaimed) => info!("reclaimed {reclaimed} bytes of the packed objects"),
+                    Err(e) => warn!("failed to compact the packed objects: {e}"),
+                }
+            }
+        });
+    }
+
+    /// Writes the pending records to the journal, until the given sequence number.
+    ///
+    /// The records which are pending meanwhile are committed together by the first writer.
+    async fn commit(&self, seq: u64) -> Result<()> {
+        let mut journal = self.journal.lock().await;
+
+        let (records, active, written) = {
+            let mut state = self.state.lock().await;
+            if state.committed >= seq {
+                return Ok(());
+            }
+
+            let active = match &state.active {
+                Some((_, file)) => Some(file.try_clone().await?),
+                None => None,
+            };
+            (::core::mem::take(&mut state.pending), active, state.written)
+        };
+
+        // the objects should be durable before their records
+        // (the sealed segments are synced when rotated)
+        let result = async {
+            if let Some(file) = active {
+                file.sync_data().await?;
+            }
+            for record in &records {
+                journal.append(record).await?;
+            }
+            journal.sync().await
+        }
+        .await;
+
+        let mut state = self.state.lock().await;
+        match result {
+            Ok(()) => {
+                state.committed = written;
+                Ok(())
+            }
+            Err(e) => {
+                // retry the records on the next commit, which are replayed idempotently
+                state.pending.splice(0..0, records);
+                Err(e)
+            }
+        }
+    }
+
+    /// Rewrites the segments which are mostly garbage, and returns the number of reclaimed bytes.
+    async fn compact(&self) -> Result<u64> {
+        let ids: Vec<_> = {
+            let state = self.state.lock().await;
+            state
+                .segments
+                .keys()
+                .copied()
+                .filter(|id| state.is_compactable(*id))
+                .collect()
+        };
+
+        let mut reclaimed = 0;
+        for id in ids {
+            reclaimed += self.compact_segment(id).await?;
+        }
+        Ok(reclaimed)
+    }
+
+    /// Moves the live objects of the segment into the active one, and removes the segment.
+    ///
+    /// The lock is held only while moving each object, so that the writes are not blocked.
+    async fn compact_segment(&self, id: u32) -> Result<u64> {
+        // collect the live objects
+        let (reclaimed, mut moved) = {
+            let state = self.state.lock().await;
+            let reclaimed = state
+                .segments
+                .get(&id)
+                .map(|segment| segment.size - segment.live)
+                .unwrap_or_default();
+            let moved: Vec<_> = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.segment == id)
+                .map(|(key, entry)| (*key, *entry))
+                .collect();
+            (reclaimed, moved)
+        };
+        moved.sort_unstable_by_key(|(_, entry)| entry.offset);
+
+        // move the live objects into the active segment
+        let mut seq = 0;
+        if !moved.is_empty() {
+            let mut file = File::open(self.to_path_segment(id)).await?;
+            let mut data = vec![];
+            for (key, entry) in moved {
+                data.clear();
+                file.seek(SeekFrom::Start(entry.offset)).await?;
+                (&mut file).take(entry.len).read_to_end(&mut data).await?;
+                if data.len() as u64 != entry.len {
+                    bail!("the segment is truncated: {id}");
+                }
+
+                let mut state = self.state.lock().await;
+
+                // skip the object which has been removed or replaced meanwhile
+                if state.entries.get(&key) != Some(&entry) {
+                    continue;
+                }
+
+                let (segment, offset) = self.append(&mut state, &data).await?;
+                let entry = PackEntry {
+                    segment,
+                    offset,
+                    ..entry
+                };
+                seq = state.insert(key, entry);
+            }
+        }
+
+        // the moved objects should be durable before the segment is removed
+        self.commit(seq).await?;
+
+        // remove the segment
+        {
+            let mut state = self.state.lock().await;
+            if state.entries.values().any(|entry| entry.segment == id) {
+                return Ok(0);
+            }
+            state.segments.remove(&id);
+        }
+        match tokio::fs::remove_file(self.to_path_segment(id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(reclaimed)
+    }
+
+    /// Appends the data to the active segment, and returns its location.
+    ///
+    /// The data is not synced until committed.
+    async fn append(&self, state: &mut PackState, data: &[u8]) -> Result<(u32, u64)> {
+        let len = data.len() as u64;
+
+        // rotate the active segment if it is full
+        let is_full = match &state.active {
+            Some((id, _)) => {
+                let size = state.segments.get(id).map(|segment| segment.size);
+                size.unwrap_or_default() + len > self.segment_size
+            }
+            None => true,
+        };
+        if is_full {
+            // the commits sync only the active segment
+            if let Some((_, file)) = state.active.take() {
+                file.sync_data().await?;
+            }
+
+            let id = state
+                .segments
+                .keys()
+                .next_back()
+                .map(|id| id + 1)
+                .unwrap_or_default();
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.to_path_segment(id))
+                .await?;
+
+            state.segments.insert(id, Segment::default());
+            state.active = Some((id, file));
+        }
+
+        // write to the segment
+        let (id, file) = state
+            .active
+            .as_mut()
+            .ok_or_else(|| anyhow!("no active segment"))?;
+        let id = *id;
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        let segment = state.segments.entry(id).or_default();
+        let offset = segment.size;
+        segment.size += len;
+        Ok((id, offset))
+    }
+}
+
+impl PackState {
+    /// Registers the location of the object, replacing the previous one.
+    ///
+    /// Returns the sequence number of the record to be committed.
+    fn insert(&mut self, key: PackKey, entry: PackEntry) -> u64 {
+        // apply to the entries
+        if let Some(entry) = self.entries.insert(key, entry) {
+            if let Some(segment) = self.segments.get_mut(&entry.segment) {
+                segment.live -= entry.len;
+            }
+        }
+        self.segments.entry(entry.segment).or_default().live += entry.len;
+
+        self.push(Record::Add { key, entry })
+    }
+
+    /// Checks whether at least half of the sealed segment is garbage.
+    fn is_compactable(&self, id: u32) -> bool {
+        let is_active = matches!(&self.active, Some((active, _)) if *active == id);
+        match self.segments.get(&id) {
+            Some(segment) => !is_active && segment.live * 2 <= segment.size,
+            None => false,
+        }
+    }
+
+    /// Queues the record to be written to the journal, and returns its sequence number.
+    fn push(&mut self, record: Record) -> u64 {
+        self.pending.push(record);
+        self.written += 1;
+        self.written
+    }
+}
+
+impl PackKey {
+    fn new(account: &AccountRef, hash: &Hash) -> Self {
+        Self {
+            account: *account,
+            hash: *hash,
+        }
+    }
+}
+
+enum Record {
+    Add { key: PackKey, entry: PackEntry },
+    Remove { key: PackKey },
+}
+
+impl ::core::fmt::Display for Record {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        match self {
+            Self::Add { key, entry } => writeln!(
+                f,
+                "+ {} {} {} {} {} {}",
+                key.account.to_string(),
+                key.hash.to_string(),
+                entry.segment,
+                entry.offset,
+                entry.len,
+                entry.created_date.to_rfc3339(),
+            ),
+            Self::Remove { key } => {
+                writeln!(f, "- {} {}", key.account.to_string(), key.hash.to_string(),)
+            }
+        }
+    }
+}
+
+impl FromStr for Record {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut tokens = JournalTokens::new("pack", line);
+
+        let op = tokens.next_token()?;
+        let key = PackKey {
+            account: tokens.next_token()?.parse()?,
+            hash: tokens.next_token()?.parse()?,
+        };
+
+        match op {
+            "+" => Ok(Self::Add {
+                key,
+                entry: PackEntry {
+                    segment: tokens.next_token()?.parse()?,
+                    offset: tokens.next_token()?.parse()?,
+                    len: tokens.next_token()?.parse()?,
+                    created_date: chrono::DateTime::parse_from_rfc3339(tokens.next_token()?)?
+                        .with_timezone(&Utc),
+                },
+            }),
+            "-" => Ok(Self::Remove { key }),
+            op => bail!("unknown pack operation: {op:?}"),
+        }
+    }
+}
+
+fn parse_segment_name(name: &str) -> Option<u32> {
+    name.strip_suffix(SEGMENT_EXTENSION)?.parse().ok()
+}
+
+const INDEX_FILE: &str = "index";
+
+const SEGMENT_EXTENSION: &str = ".seg";
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ipis::{
+        core::account::Account,
+        futures::future::try_join_all,
+        tokio::{self, io::AsyncReadExt},
+    };
+
+    use super::*;
+
+    async fn read(pack: &IpsisPack, account: &AccountRef, hash: &Hash) -> Result<Vec<u8>> {
+        let (file, entry) = pack
+            .open_entry(account, hash)
+            .await?
+            .ok_or_else(|| anyhow!("no such object"))?;
+
+        let mut data = vec![];
+        file.take(entry.len).read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    /// Drops the pack once the compaction releases it, so that the journal can be reopened.
+    async fn close(pack: IpsisPack) {
+        while Arc::strong_count(&pack.inner) > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(pack);
+    }
+
+    #[tokio::test]
+    async fn test_replay_concurrent_puts() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let account = Account::generate().account_ref();
+        let objects: Vec<_> = (0..16u8)
+            .map(|i| (Hash::with_bytes(&[i]), vec![i; 8]))
+            .collect();
+
+        let pack = IpsisPack::open(dir.path().into(), 64).await?;
+        try_join_all(
+            objects
+                .iter()
+                .map(|(hash, data)| pack.put(&account, hash, data)),
+        )
+        .await?;
+        close(pack).await;
+
+        let pack = IpsisPack::open(dir.path().into(), 64).await?;
+        for (hash, data) in &objects {
+            assert_eq!(&read(&pack, &account, hash).await?, data);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compact_in_background() -> Result<()> {
+        let dir = ::tempfile::tempdir()?;
+        let account = Account::generate().account_ref();
+        let (a, b, c) = (
+            Hash::with_bytes(b"a"),
+            Hash::with_bytes(b"b"),
+            Hash::with_bytes(b"c"),
+        );
+
+        // the segment 0 keeps `a` and `b`, and the segment 1 keeps `c`
+        let pack = IpsisPack::open(dir.path().into(), 16).await?;
+        pack.put(&account, &a, b"aaaaaaaa").await?;
+        pack.put(&account, &b, b"bbbbbbbb").await?;
+        pack.put(&account, &c, b"cccccccc").await?;
+        assert_eq!(
+            pack.get(&account, &b).await.map(|entry| entry.segment),
+            Some(0)
+        );
+
+        // the segment 0 becomes half garbage
+        assert!(pack.remove(&account, &a).await?);
+
+        let path_segment = pack.inner.to_path_segment(0);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while tokio::fs::metadata(&path_segment).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        assert_eq!(
+            pack.get(&account, &b).await.map(|entry| entry.segment),
+            Some(1)
+        );
+        close(pack).await;
+
+        let pack = IpsisPack::open(dir.path().into(), 16).await?;
+        assert!(pack.get(&account, &a).await.is_none());
+        assert_eq!(read(&pack, &account, &b).await?, b"bbbbbbbb");
+        assert_eq!(read(&pack, &account, &c).await?, b"cccccccc");
+        Ok(())
+    }
+}
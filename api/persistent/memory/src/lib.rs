@@ -0,0 +1,518 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{Arc, PoisonError, RwLock as SyncRwLock},
+};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{bail, Result},
+        chrono::{DateTime, Utc},
+        value::hash::Hash,
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        sync::RwLock,
+    },
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+
+/// A persistent storage which holds the objects in memory.
+///
+/// The objects are lost when the storage is dropped,
+/// so it is meant for the tests and the benchmarks.
+pub struct IpsisPersistentStorageImpl {
+    state: RwLock<MemoryState>,
+    capacity: Option<u64>,
+    fault_hook: SyncRwLock<Option<IpsisMemoryFaultHook>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    accounts: HashMap<AccountRef, BTreeMap<String, MemoryObject>>,
+    size: u64,
+}
+
+impl MemoryState {
+    /// Returns the length of the stored object, or zero if it is not stored.
+    fn len_of(&self, account: &AccountRef, key: &str) -> u64 {
+        self.accounts
+            .get(account)
+            .and_then(|objects| objects.get(key))
+            .map(|object| object.data.len() as u64)
+            .unwrap_or_default()
+    }
+}
+
+struct MemoryObject {
+    hash: Hash,
+    data: Arc<[u8]>,
+    created_date: DateTime<Utc>,
+}
+
+/// The operations of the storage, which are passed to the fault hook.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IpsisMemoryOperation {
+    Get,
+    Put,
+    Contains,
+    Stat,
+    Delete,
+    List,
+}
+
+/// A hook which is called before each operation, and fails the operation if it returns an error.
+pub type IpsisMemoryFaultHook =
+    Box<dyn Fn(IpsisMemoryOperation, &AccountRef, Option<&Path>) -> Result<()> + Send + Sync>;
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisPersistentStorageImpl {
+    /// The maximum total length of the objects, or `None` if unlimited.
+    type GenesisArgs = Option<u64>;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        Self::genesis(infer("ipsis_memory_capacity").ok()).await
+    }
+
+    async fn genesis(
+        capacity: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Ok(Self::new(capacity))
+    }
+}
+
+impl IpsisPersistentStorageImpl {
+    pub fn new(capacity: Option<u64>) -> Self {
+        Self {
+            state: Default::default(),
+            capacity,
+            fault_hook: Default::default(),
+        }
+    }
+
+    /// Returns the total length of the stored objects.
+    pub async fn size(&self) -> u64 {
+        self.state.read().await.size
+    }
+
+    /// Replaces the fault hook, or removes it if `None`.
+    pub fn set_fault_hook(&self, hook: Option<IpsisMemoryFaultHook>) {
+        *self
+            .fault_hook
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = hook;
+    }
+
+    /// Flips the first byte of the stored object, to simulate the silent corruption.
+    pub async fn corrupt(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        let mut state = self.state.write().await;
+        let object = match state
+            .accounts
+            .get_mut(account)
+            .and_then(|objects| objects.get_mut(&path.value.to_string()))
+        {
+            Some(object) => object,
+            None => bail!("no such object: {}", path.value.to_string()),
+        };
+
+        let mut data = object.data.to_vec();
+        match data.first_mut() {
+            Some(byte) => *byte ^= 0xff,
+            None => bail!("the object is empty: {}", path.value.to_string()),
+        }
+        object.data = data.into();
+        Ok(())
+    }
+
+    fn check_fault(
+        &self,
+        operation: IpsisMemoryOperation,
+        account: &AccountRef,
+        path: Option<&Path>,
+    ) -> Result<()> {
+        match &*self
+            .fault_hook
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(hook) => hook(operation, account, path),
+            None => Ok(()),
+        }
+    }
+
+    async fn load(&self, account: &AccountRef, path: &Path) -> Result<Arc<[u8]>> {
+        let state = self.state.read().await;
+        match state
+            .accounts
+            .get(account)
+            .and_then(|objects| objects.get(&path.value.to_string()))
+        {
+            Some(object) => Ok(object.data.clone()),
+            None => bail!("no such object: {}", path.value.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
+    fn protocol(&self) -> &'static str {
+        "memory"
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        false
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        true
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        self.check_fault(IpsisMemoryOperation::Get, account, Some(path))?;
+
+        let data = self.load(account, path).await?;
+        writer.write_all(&data).await.map_err(Into::into)
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        self.check_fault(IpsisMemoryOperation::Get, account, Some(path))?;
+
+        let data = self.load(account, path).await?;
+        let end = offset + len;
+        if end > data.len() as u64 {
+            bail!(
+                "the range {offset}..{end} exceeds the length {}",
+                data.len(),
+            );
+        }
+        writer
+            .write_all(&data[offset as usize..end as usize])
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        self.check_fault(IpsisMemoryOperation::Put, account, Some(path))?;
+
+        if let Some(capacity) = self.capacity {
+            if path.len > capacity {
+                bail!(
+                    "the object is larger than the capacity: {}/{capacity}",
+                    path.len
+                );
+            }
+        }
+
+        // receive data, without buffering more than the remaining capacity
+        let key = path.value.to_string();
+        let limit = match self.capacity {
+            Some(capacity) => {
+                let state = self.state.read().await;
+                let used = state.size - state.len_of(account, &key);
+                capacity.saturating_sub(used) + 1
+            }
+            None => u64::MAX,
+        };
+        let mut data = vec![];
+        (&mut *reader).take(limit).read_to_end(&mut data).await?;
+        let len = data.len() as u64;
+
+        // store data
+        let mut state = self.state.write().await;
+        let len_old = state.len_of(account, &key);
+        let size = state.size - len_old + len;
+        if let Some(capacity) = self.capacity {
+            if size > capacity {
+                bail!("the capacity is exceeded: {size}/{capacity}");
+            }
+        }
+
+        state.accounts.entry(*account).or_default().insert(
+            key,
+            MemoryObject {
+                hash: path.value,
+                data: data.into(),
+                created_date: Utc::now(),
+            },
+        );
+        state.size = size;
+        Ok(Ok(()))
+    }
+
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        self.check_fault(IpsisMemoryOperation::Contains, account, Some(path))?;
+
+        let state = self.state.read().await;
+        Ok(state
+            .accounts
+            .get(account)
+            .map(|objects| objects.contains_key(&path.value.to_string()))
+            .unwrap_or_default())
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        self.check_fault(IpsisMemoryOperation::Stat, account, Some(path))?;
+
+        let state = self.state.read().await;
+        match state
+            .accounts
+            .get(account)
+            .and_then(|objects| objects.get(&path.value.to_string()))
+        {
+            Some(object) => Ok(Stat {
+                path: Path {
+                    value: object.hash,
+                    len: object.data.len() as u64,
+                },
+                created_date: Some(object.created_date.into()),
+                accessed_date: None,
+                protocol: self.protocol().into(),
+                content_type: None,
+            }),
+            None => bail!("no such object: {}", path.value.to_string()),
+        }
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        self.check_fault(IpsisMemoryOperation::Delete, account, Some(path))?;
+
+        let mut state = self.state.write().await;
+        match state
+            .accounts
+            .get_mut(account)
+            .and_then(|objects| objects.remove(&path.value.to_string()))
+        {
+            Some(object) => {
+                state.size -= object.data.len() as u64;
+                Ok(())
+            }
+            None => bail!("no such object: {}", path.value.to_string()),
+        }
+    }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        self.check_fault(IpsisMemoryOperation::List, account, None)?;
+
+        let state = self.state.read().await;
+        let objects = match state.accounts.get(account) {
+            Some(objects) => objects,
+            None => return Ok(Default::default()),
+        };
+
+        // collect the entries after the cursor
+        let lower = match &query.cursor {
+            Some(cursor) => Bound::Excluded(cursor.as_str()),
+            None => Bound::Unbounded,
+        };
+        let mut entries: Vec<_> = objects
+            .range::<str, _>((lower, Bound::Unbounded))
+            .take(query.limit() + 1)
+            .collect();
+
+        // paginate
+        let next_cursor = if entries.len() > query.limit() {
+            entries.truncate(query.limit());
+            entries.last().map(|(name, _)| (*name).clone())
+        } else {
+            None
+        };
+
+        // pack data
+        Ok(ListPage {
+            paths: entries
+                .into_iter()
+                .map(|(_, object)| Path {
+                    value: object.hash,
+                    len: object.data.len() as u64,
+                })
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        let state = self.state.read().await;
+        Ok(state
+            .accounts
+            .iter()
+            .filter(|(_, objects)| !objects.is_empty())
+            .map(|(account, _)| *account)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipis::{
+        core::{account::Account, value::hash::Hash},
+        tokio,
+    };
+
+    use super::*;
+
+    fn path(data: &[u8]) -> Path {
+        Path {
+            value: Hash::with_bytes(data),
+            len: data.len() as u64,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() -> Result<()> {
+        let storage = IpsisPersistentStorageImpl::new(None);
+        let (a, b) = (
+            Account::generate().account_ref(),
+            Account::generate().account_ref(),
+        );
+        let path = path(b"hello");
+
+        for account in [&a, &b] {
+            let mut reader = &b"hello"[..];
+            assert!(storage.put_raw(account, &path, &mut reader).await?.is_ok());
+        }
+        storage.delete(&b, &path).await?;
+
+        // the accounts without objects are not listed
+        assert_eq!(storage.namespaces().await?, [a]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_capacity() -> Result<()> {
+        let storage = IpsisPersistentStorageImpl::new(Some(12));
+        let account = Account::generate().account_ref();
+        let (path_a, path_b) = (path(b"aaaaaaaa"), path(b"bbbbbbbb"));
+
+        let mut reader = &b"aaaaaaaa"[..];
+        assert!(storage
+            .put_raw(&account, &path_a, &mut reader)
+            .await?
+            .is_ok());
+        let mut reader = &b"bbbbbbbb"[..];
+        assert!(storage
+            .put_raw(&account, &path_b, &mut reader)
+            .await
+            .is_err());
+        assert!(!storage.contains(&account, &path_b).await?);
+
+        // the overwritten and the deleted objects are not counted
+        let mut reader = &b"aaaaaaaa"[..];
+        assert!(storage
+            .put_raw(&account, &path_a, &mut reader)
+            .await?
+            .is_ok());
+        assert_eq!(storage.size().await, 8);
+        storage.delete(&account, &path_a).await?;
+        assert_eq!(storage.size().await, 0);
+
+        let mut reader = &b"bbbbbbbb"[..];
+        assert!(storage
+            .put_raw(&account, &path_b, &mut reader)
+            .await?
+            .is_ok());
+
+        // the endless stream is not buffered beyond the capacity
+        let mut reader = tokio::io::repeat(0);
+        assert!(storage
+            .put_raw(&account, &path(b"cccc"), &mut reader)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fault_hook() -> Result<()> {
+        let storage = IpsisPersistentStorageImpl::new(None);
+        let account = Account::generate().account_ref();
+        let path = path(b"hello");
+
+        let mut reader = &b"hello"[..];
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+
+        // only the hooked operations fail
+        storage.set_fault_hook(Some(Box::new(|operation, _, _| {
+            if operation == IpsisMemoryOperation::Get {
+                bail!("the storage is down")
+            }
+            Ok(())
+        })));
+        assert!(storage
+            .get_raw(&account, &path, &mut Vec::<u8>::new())
+            .await
+            .is_err());
+        assert!(storage.contains(&account, &path).await?);
+
+        storage.set_fault_hook(None);
+        let mut buf = vec![];
+        storage
+            .get_raw_range(&account, &path, 1, 3, &mut buf)
+            .await?;
+        assert_eq!(buf, b"ell");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_pages() -> Result<()> {
+        let storage = IpsisPersistentStorageImpl::new(None);
+        let account = Account::generate().account_ref();
+
+        let mut paths = vec![];
+        for index in 0..10u8 {
+            let data = [index; 4];
+            let path = path(&data);
+            let mut reader = ::std::io::Cursor::new(data);
+            assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+            paths.push(path);
+        }
+
+        let mut listed = vec![];
+        let mut query = ListQuery {
+            cursor: None,
+            limit: 3,
+        };
+        loop {
+            let page = storage.list(&account, &query).await?;
+            assert!(page.paths.len() <= 3);
+            listed.extend(page.paths);
+
+            query.cursor = page.next_cursor;
+            if query.cursor.is_none() {
+                break;
+            }
+        }
+
+        listed.sort_by_key(|path| path.value.to_string());
+        paths.sort_by_key(|path| path.value.to_string());
+        assert_eq!(listed, paths);
+        Ok(())
+    }
+}
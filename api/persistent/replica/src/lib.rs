@@ -0,0 +1,642 @@
+use std::{collections::HashMap, str::FromStr};
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{anyhow, bail, Error, Result},
+        value::hash::Hasher,
+    },
+    env::{infer, Infer},
+    futures::future::{join_all, try_join_all},
+    log::warn,
+    path::Path,
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    },
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+
+/// A persistent storage which replicates the objects over multiple inner storages.
+///
+/// The writes succeed when the write quorum of the replicas has stored the object,
+/// and the reads fall back across the replicas.
+/// The missing copies can be restored with [`IpsisPersistentStorageImpl::repair`],
+/// which is run over all objects by the `repair` command of the manager.
+pub struct IpsisPersistentStorageImpl<S> {
+    replicas: Vec<S>,
+    write_quorum: usize,
+}
+
+#[async_trait]
+impl<'a, S> Infer<'a> for IpsisPersistentStorageImpl<S>
+where
+    Self: Send,
+    S: Infer<'a, GenesisResult = S> + IpsisPersistentStorage + Send,
+    <S as Infer<'a>>::GenesisArgs: FromStr + Send + Sized,
+{
+    type GenesisArgs = Vec<<S as Infer<'a>>::GenesisArgs>;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        // the genesis arguments of each replica, separated by commas
+        let replicas: String = infer("ipsis_replicas")?;
+        let args = replicas
+            .split(',')
+            .map(str::trim)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| {
+                arg.parse()
+                    .map_err(|_| anyhow!("malformed replica: {arg:?}"))
+            })
+            .collect::<Result<_>>()?;
+        Self::genesis(args).await
+    }
+
+    async fn genesis(
+        args: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        Self::new(try_join_all(args.into_iter().map(S::genesis)).await?)
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S> {
+    /// Wraps the replicas, with the write quorum which defaults to the majority of them.
+    pub fn new(replicas: Vec<S>) -> Result<Self>
+    where
+        S: IpsisPersistentStorage,
+    {
+        if replicas.is_empty() {
+            bail!("no replicas are given");
+        }
+        if replicas.iter().any(|replica| {
+            replica.use_hash_as_native() != replicas[0].use_hash_as_native()
+                || replica.use_account_as_namespace() != replicas[0].use_account_as_namespace()
+        }) {
+            bail!("the replicas should agree on the hashes and the namespaces of the objects");
+        }
+
+        let write_quorum = infer("ipsis_replica_write_quorum").unwrap_or(replicas.len() / 2 + 1);
+        if write_quorum == 0 || write_quorum > replicas.len() {
+            bail!(
+                "the write quorum should be in 1..={}, but given {write_quorum}",
+                replicas.len(),
+            );
+        }
+
+        Ok(Self {
+            replicas,
+            write_quorum,
+        })
+    }
+
+    pub fn replicas(&self) -> &[S] {
+        &self.replicas
+    }
+
+    pub fn write_quorum(&self) -> usize {
+        self.write_quorum
+    }
+}
+
+impl<S> IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    /// Reads the range from the replicas in order.
+    ///
+    /// If a replica fails while streaming, the rest of the range is read from the next one.
+    async fn read_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        mut offset: u64,
+        end: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let mut errors = vec![];
+        for replica in &self.replicas {
+            // create a channel
+            let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+            let recv = async move {
+                let mut tx = tx;
+                replica
+                    .get_raw_range(account, path, offset, end - offset, &mut tx)
+                    .await
+            };
+            let writer = &mut *writer;
+            let forward = async move {
+                let mut rx = rx;
+                tokio::io::copy(&mut rx, writer).await
+            };
+
+            let (recv, forward) = tokio::join!(recv, forward);
+            offset += forward?;
+
+            match recv {
+                Ok(()) if offset == end => return Ok(()),
+                Ok(()) => errors.push(anyhow!("the data are truncated: {offset}/{end}")),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        bail!(
+            "no replica could serve {}: {}",
+            path.value.to_string(),
+            fmt_errors(&errors),
+        )
+    }
+
+    /// Copies the object to the replicas which miss it, from the first intact copy.
+    ///
+    /// Returns the number of the restored copies.
+    pub async fn repair(&self, account: &AccountRef, path: &Path) -> Result<usize> {
+        let contains = try_join_all(
+            self.replicas
+                .iter()
+                .map(|replica| replica.contains(account, path)),
+        )
+        .await?;
+
+        let mut errors = vec![];
+        let mut source = None;
+        for (replica, _) in self
+            .replicas
+            .iter()
+            .zip(&contains)
+            .filter(|(_, contains)| **contains)
+        {
+            match self.verify_copy(replica, account, path).await {
+                Ok(()) => {
+                    source = Some(replica);
+                    break;
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        let source = match source {
+            Some(source) => source,
+            None if errors.is_empty() => bail!("no replica holds {}", path.value.to_string()),
+            None => bail!(
+                "no replica holds the intact {}: {}",
+                path.value.to_string(),
+                fmt_errors(&errors),
+            ),
+        };
+
+        let mut num_restored = 0;
+        for (replica, _) in self
+            .replicas
+            .iter()
+            .zip(contains)
+            .filter(|(_, contains)| !contains)
+        {
+            // create a channel
+            let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+            let recv = async move {
+                let mut tx = tx;
+                source.get_raw(account, path, &mut tx).await
+            };
+            let send = async move {
+                let mut rx = rx;
+                replica.put_raw(account, path, &mut rx).await
+            };
+
+            let (recv, send) = tokio::join!(recv, send);
+            if let Err(e) = recv {
+                // do not leave the partial copy
+                replica.delete(account, path).await.ok();
+                return Err(e);
+            }
+            if let Err(path) = send? {
+                bail!("the copy has been corrupted: {}", path.value.to_string());
+            }
+            num_restored += 1;
+        }
+        Ok(num_restored)
+    }
+
+    /// Hashes the copy of the replica, and checks whether it is intact.
+    ///
+    /// The data which have been transformed by the outer storages, such as encryption,
+    /// cannot be verified against the path, so they are checked against the stored length.
+    async fn verify_copy(&self, replica: &S, account: &AccountRef, path: &Path) -> Result<()> {
+        // create a channel
+        let (tx, rx) = tokio::io::duplex(CHUNK_SIZE);
+
+        let recv = async move {
+            let mut tx = tx;
+            replica.get_raw(account, path, &mut tx).await
+        };
+        let hash = async move {
+            let mut rx = rx;
+            let mut buf = vec![0; CHUNK_SIZE];
+            let mut hasher = Hasher::default();
+            loop {
+                let len = rx.read(&mut buf).await?;
+                if len == 0 {
+                    break Result::<_, Error>::Ok(hasher);
+                }
+                hasher.update(&buf[..len]);
+            }
+        };
+
+        let (recv, hash) = tokio::join!(recv, hash);
+        recv?;
+        let hasher = hash?;
+
+        let len = hasher.len() as u64;
+        let is_intact = if len == path.len {
+            // the native hashes are verified by the replica itself
+            replica.use_hash_as_native() || hasher.finalize() == path.value
+        } else {
+            replica.stat(account, path).await?.path.len == len
+        };
+        if is_intact {
+            Ok(())
+        } else {
+            bail!("the copy has been corrupted: {}", path.value.to_string())
+        }
+    }
+
+    /// Repairs all objects of the account which are stored on any replica.
+    ///
+    /// An object which is deleted while being repaired may be left on a replica,
+    /// which is reported as orphaned by the scrub.
+    ///
+    /// Returns the number of the restored copies.
+    pub async fn repair_all(&self, account: &AccountRef) -> Result<usize> {
+        // collect the objects of all replicas
+        let mut paths = HashMap::new();
+        for replica in &self.replicas {
+            let mut query = ListQuery::default();
+            loop {
+                let page = replica.list(account, &query).await?;
+                for path in page.paths {
+                    paths.entry(path.value).or_insert(path);
+                }
+
+                match page.next_cursor {
+                    Some(cursor) => query.cursor = Some(cursor),
+                    None => break,
+                }
+            }
+        }
+
+        let mut num_restored = 0;
+        for path in paths.values() {
+            match self.repair(account, path).await {
+                Ok(num) => num_restored += num,
+                Err(e) => warn!("failed to repair {}: {e}", path.value.to_string()),
+            }
+        }
+        Ok(num_restored)
+    }
+}
+
+#[async_trait]
+impl<S> IpsisPersistentStorage for IpsisPersistentStorageImpl<S>
+where
+    S: IpsisPersistentStorage + Send + Sync,
+{
+    /// The protocol of the primary replica.
+    fn protocol(&self) -> &'static str {
+        self.replicas[0].protocol()
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        self.replicas[0].use_hash_as_native()
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        self.replicas[0].use_account_as_namespace()
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let len = self.stat(account, path).await?.path.len;
+        self.read_range(account, path, 0, len, writer).await
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        self.read_range(account, path, offset, offset + len, writer)
+            .await
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // create the channels
+        let (txs, rxs): (Vec<_>, Vec<_>) = self
+            .replicas
+            .iter()
+            .map(|_| tokio::io::duplex(CHUNK_SIZE))
+            .unzip();
+
+        let tee = async move {
+            let mut txs: Vec<Option<DuplexStream>> = txs.into_iter().map(Some).collect();
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let len = reader.read(&mut buf).await?;
+                if len == 0 {
+                    break Result::<_, Error>::Ok(());
+                }
+
+                // the failed replicas are left behind
+                let chunk = &buf[..len];
+                join_all(txs.iter_mut().map(|tx| async move {
+                    if let Some(stream) = tx {
+                        if stream.write_all(chunk).await.is_err() {
+                            *tx = None;
+                        }
+                    }
+                }))
+                .await;
+                if txs.iter().all(Option::is_none) {
+                    break Ok(());
+                }
+            }
+        };
+        let send = join_all(
+            self.replicas
+                .iter()
+                .zip(rxs)
+                .map(|(replica, rx)| async move {
+                    let mut rx = rx;
+                    replica.put_raw(account, path, &mut rx).await
+                }),
+        );
+
+        let (tee, send) = tokio::join!(tee, send);
+        tee?;
+
+        let mut num_stored = 0;
+        let mut errors = vec![];
+        for result in send {
+            match result {
+                Ok(Ok(())) => num_stored += 1,
+                Ok(Err(path)) => return Ok(Err(path)),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if num_stored < self.write_quorum {
+            bail!(
+                "the write quorum is not reached: {num_stored}/{}: {}",
+                self.write_quorum,
+                fmt_errors(&errors),
+            );
+        }
+        if !errors.is_empty() {
+            warn!(
+                "{} of {} replicas failed to store {}: {}",
+                errors.len(),
+                self.replicas.len(),
+                path.value.to_string(),
+                fmt_errors(&errors),
+            );
+        }
+        Ok(Ok(()))
+    }
+
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        let mut errors = vec![];
+        for replica in &self.replicas {
+            match replica.contains(account, path).await {
+                Ok(true) => return Ok(true),
+                Ok(false) => continue,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.len() == self.replicas.len() {
+            bail!("no replica is available: {}", fmt_errors(&errors))
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        let mut errors = vec![];
+        for replica in &self.replicas {
+            match replica.stat(account, path).await {
+                Ok(stat) => return Ok(stat),
+                Err(e) => errors.push(e),
+            }
+        }
+        bail!(
+            "no replica could stat {}: {}",
+            path.value.to_string(),
+            fmt_errors(&errors),
+        )
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        let mut errors = vec![];
+        for replica in &self.replicas {
+            let result = match replica.contains(account, path).await {
+                Ok(true) => replica.delete(account, path).await,
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            bail!(
+                "failed to delete {} from {} replicas: {}",
+                path.value.to_string(),
+                errors.len(),
+                fmt_errors(&errors),
+            )
+        }
+    }
+
+    /// Lists the objects of the first available replica,
+    /// as the cursors cannot be shared across the replicas.
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        let mut errors = vec![];
+        for replica in &self.replicas {
+            match replica.list(account, query).await {
+                Ok(page) => return Ok(page),
+                Err(e) => errors.push(e),
+            }
+        }
+        bail!("no replica could list: {}", fmt_errors(&errors))
+    }
+
+    /// Returns the namespaces of all available replicas,
+    /// as a namespace may be stored only on some of them.
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        let mut namespaces = vec![];
+        let mut errors = vec![];
+        for replica in &self.replicas {
+            match replica.namespaces().await {
+                Ok(replica_namespaces) => namespaces.extend(replica_namespaces),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.len() == self.replicas.len() {
+            bail!("no replica could list: {}", fmt_errors(&errors))
+        }
+        if !errors.is_empty() {
+            warn!(
+                "{} of {} replicas failed to list the namespaces: {}",
+                errors.len(),
+                self.replicas.len(),
+                fmt_errors(&errors),
+            );
+        }
+
+        namespaces.sort_unstable_by_key(ToString::to_string);
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+
+    async fn repair(&self, account: &AccountRef) -> Result<usize> {
+        self.repair_all(account).await
+    }
+}
+
+fn fmt_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+const CHUNK_SIZE: usize = 4_096;
+
+#[cfg(test)]
+mod tests {
+    use ipis::{
+        core::{account::Account, value::hash::Hash},
+        tokio,
+    };
+    use ipsis_api_persistent_memory::{
+        IpsisMemoryOperation, IpsisPersistentStorageImpl as IpsisMemory,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_repair_missing_copy() -> Result<()> {
+        let storage = IpsisPersistentStorageImpl::new(vec![
+            IpsisMemory::new(None),
+            IpsisMemory::new(None),
+            IpsisMemory::new(None),
+        ])?;
+        let account = Account::generate().account_ref();
+        let data = vec![42; 3 * CHUNK_SIZE];
+        let path = Path {
+            value: Hash::with_bytes(&data),
+            len: data.len() as u64,
+        };
+
+        // the second replica is down while storing
+        storage.replicas()[1].set_fault_hook(Some(Box::new(|operation, _, _| {
+            if operation == IpsisMemoryOperation::Put {
+                bail!("the replica is down")
+            }
+            Ok(())
+        })));
+        let mut reader = ::std::io::Cursor::new(data.clone());
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+        assert!(!storage.replicas()[1].contains(&account, &path).await?);
+
+        storage.replicas()[1].set_fault_hook(None);
+        assert_eq!(storage.repair(&account).await?, 1);
+        for replica in storage.replicas() {
+            let mut buf = vec![];
+            replica.get_raw(&account, &path, &mut buf).await?;
+            assert_eq!(buf, data);
+        }
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_repair_from_intact_copy() -> Result<()> {
+        let storage = IpsisPersistentStorageImpl::new(vec![
+            IpsisMemory::new(None),
+            IpsisMemory::new(None),
+            IpsisMemory::new(None),
+        ])?;
+        let account = Account::generate().account_ref();
+        let data = vec![42; 3 * CHUNK_SIZE];
+        let path = Path {
+            value: Hash::with_bytes(&data),
+            len: data.len() as u64,
+        };
+
+        // the first copy is corrupted, and the third one is lost
+        let mut reader = ::std::io::Cursor::new(data.clone());
+        assert!(storage.put_raw(&account, &path, &mut reader).await?.is_ok());
+        storage.replicas()[0].corrupt(&account, &path).await?;
+        storage.replicas()[2].delete(&account, &path).await?;
+
+        assert_eq!(storage.repair(&account).await?, 1);
+        let mut buf = vec![];
+        storage.replicas()[2]
+            .get_raw(&account, &path, &mut buf)
+            .await?;
+        assert_eq!(buf, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_of_all_replicas() -> Result<()> {
+        let storage =
+            IpsisPersistentStorageImpl::new(vec![IpsisMemory::new(None), IpsisMemory::new(None)])?;
+        let account_a = Account::generate().account_ref();
+        let account_b = Account::generate().account_ref();
+        let data = vec![42; 16];
+        let path = Path {
+            value: Hash::with_bytes(&data),
+            len: data.len() as u64,
+        };
+
+        // each replica holds a namespace of its own
+        for (replica, account) in storage.replicas().iter().zip([&account_a, &account_b]) {
+            let mut reader = ::std::io::Cursor::new(data.clone());
+            assert!(replica.put_raw(account, &path, &mut reader).await?.is_ok());
+        }
+
+        let namespaces = storage.namespaces().await?;
+        assert_eq!(namespaces.len(), 2);
+        assert!(namespaces.contains(&account_a));
+        assert!(namespaces.contains(&account_b));
+        Ok(())
+    }
+}
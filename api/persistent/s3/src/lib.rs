@@ -3,13 +3,17 @@ use ipis::{
     core::{
         account::AccountRef,
         anyhow::{bail, Result},
+        chrono::{DateTime, Utc},
     },
     env::{infer, Infer},
     path::Path,
     tokio::io::{AsyncRead, AsyncWrite},
 };
-use ipsis_api_persistent_common::IpsisPersistentStorage;
-use s3::Bucket;
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+use s3::{command::Command, request::Reqwest, request_trait::Request, Bucket};
 
 pub struct IpsisPersistentStorageImpl {
     bucket: Bucket,
@@ -67,6 +71,10 @@ impl IpsisPersistentStorageImpl {
         &self.bucket
     }
 
+    pub fn to_path_account(&self, account: &AccountRef) -> String {
+        format!("{}/", account.to_string())
+    }
+
     pub fn to_path_canonical(&self, account: &AccountRef, path: &Path) -> String {
         format!("{}/{}", account.to_string(), path.value.to_string())
     }
@@ -74,8 +82,17 @@ impl IpsisPersistentStorageImpl {
 
 #[async_trait]
 impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
-    const PROTOCOL: &'static str = "s3";
-    const USE_HASH_AS_NATIVE: bool = false;
+    fn protocol(&self) -> &'static str {
+        "s3"
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        false
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        true
+    }
 
     async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
     where
@@ -93,6 +110,38 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
         validate_http_status_code(status_code)
     }
 
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // skip empty ranges, which cannot be expressed in the `Range` header
+        if len == 0 {
+            return Ok(());
+        }
+
+        // get canonical path
+        let path = *path;
+        let path_canonical = self.to_path_canonical(account, &path);
+
+        // external call, streaming the range as `Bucket::get_object_stream` does
+        let command = Command::GetObjectRange {
+            start: offset,
+            end: Some(offset + len - 1),
+        };
+        let request = Reqwest::new(&self.bucket, &path_canonical, command);
+        let status_code = request.response_data_to_writer(writer).await?;
+
+        // validate response
+        validate_http_status_code(status_code)
+    }
+
     async fn put_raw<R>(
         &self,
         account: &AccountRef,
@@ -132,6 +181,34 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
         Ok(status_code == 200)
     }
 
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        // get canonical path
+        let path = *path;
+        let path_canonical = self.to_path_canonical(account, &path);
+
+        // external call
+        let (result, status_code) = self.bucket.head_object(path_canonical).await?;
+
+        // validate response
+        validate_http_status_code(status_code)?;
+
+        // pack data
+        Ok(Stat {
+            path: Path {
+                value: path.value,
+                len: result.content_length.unwrap_or_default().try_into()?,
+            },
+            created_date: result
+                .last_modified
+                .as_deref()
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc).into()),
+            accessed_date: None,
+            protocol: self.protocol().into(),
+            content_type: result.content_type,
+        })
+    }
+
     async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
         // get canonical path
         let path = self.to_path_canonical(account, path);
@@ -142,6 +219,70 @@ impl IpsisPersistentStorage for IpsisPersistentStorageImpl {
         // validate response
         validate_http_status_code(result.status_code())
     }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        // get canonical path
+        let prefix = self.to_path_account(account);
+
+        // external call
+        let (result, status_code) = self
+            .bucket
+            .list_page(
+                prefix.clone(),
+                Some("/".into()),
+                query.cursor.clone(),
+                None,
+                Some(query.limit()),
+            )
+            .await?;
+
+        // validate response
+        validate_http_status_code(status_code)?;
+
+        // pack data
+        Ok(ListPage {
+            paths: result
+                .contents
+                .into_iter()
+                .filter_map(|object| {
+                    Some(Path {
+                        value: object.key.strip_prefix(&prefix)?.parse().ok()?,
+                        len: object.size,
+                    })
+                })
+                .collect(),
+            next_cursor: result.next_continuation_token,
+        })
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        let mut namespaces = vec![];
+        let mut cursor = None;
+        loop {
+            // external call
+            let (result, status_code) = self
+                .bucket
+                .list_page(String::new(), Some("/".into()), cursor, None, None)
+                .await?;
+
+            // validate response
+            validate_http_status_code(status_code)?;
+
+            // the accounts are the top-level prefixes
+            namespaces.extend(
+                result
+                    .common_prefixes
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|prefix| prefix.prefix.strip_suffix('/')?.parse().ok()),
+            );
+
+            cursor = result.next_continuation_token;
+            if cursor.is_none() {
+                break Ok(namespaces);
+            }
+        }
+    }
 }
 
 fn validate_http_status_code(status_code: u16) -> Result<()> {
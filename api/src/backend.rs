@@ -0,0 +1,248 @@
+use std::str::FromStr;
+
+#[cfg(feature = "local")]
+use std::path::PathBuf;
+
+use ipis::{
+    async_trait::async_trait,
+    core::{
+        account::AccountRef,
+        anyhow::{bail, Error, Result},
+    },
+    env::{infer, Infer},
+    path::Path,
+    tokio::io::{AsyncRead, AsyncWrite},
+};
+use ipsis_api_persistent_common::{
+    common::{ListPage, ListQuery, Stat},
+    IpsisPersistentStorage,
+};
+
+#[cfg(not(any(
+    feature = "ipfs",
+    feature = "local",
+    feature = "memory",
+    feature = "s3"
+)))]
+compile_error!("at least one of the backends should be enabled: ipfs, local, memory, s3");
+
+/// A persistent storage which is selected on runtime among the enabled backends.
+///
+/// The backend is given by `ipsis_backend`, such as `local`, `local:/path/to/dir`,
+/// `local:/mnt/disk0:/mnt/disk1`, `s3`, `ipfs` or `memory`.
+pub enum IpsisPersistentStorageBackend {
+    #[cfg(feature = "ipfs")]
+    Ipfs(::ipsis_api_persistent_ipfs::IpsisPersistentStorageImpl),
+    #[cfg(feature = "local")]
+    Local(::ipsis_api_persistent_local::IpsisPersistentStorageImpl),
+    #[cfg(feature = "memory")]
+    Memory(::ipsis_api_persistent_memory::IpsisPersistentStorageImpl),
+    #[cfg(feature = "s3")]
+    S3(::ipsis_api_persistent_s3::IpsisPersistentStorageImpl),
+}
+
+/// The genesis arguments of [`IpsisPersistentStorageBackend`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IpsisPersistentStorageBackendArgs {
+    #[cfg(feature = "ipfs")]
+    Ipfs,
+    /// The directories of the objects, or the inferred ones if empty.
+    #[cfg(feature = "local")]
+    Local(Vec<PathBuf>),
+    #[cfg(feature = "memory")]
+    Memory,
+    #[cfg(feature = "s3")]
+    S3,
+}
+
+impl FromStr for IpsisPersistentStorageBackendArgs {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+
+        match (name, arg) {
+            #[cfg(feature = "ipfs")]
+            ("ipfs", None) => Ok(Self::Ipfs),
+            #[cfg(feature = "local")]
+            ("local", dirs) => Ok(Self::Local(
+                dirs.map(|dirs| ::std::env::split_paths(dirs).collect())
+                    .unwrap_or_default(),
+            )),
+            #[cfg(feature = "memory")]
+            ("memory", None) => Ok(Self::Memory),
+            #[cfg(feature = "s3")]
+            ("s3", None) => Ok(Self::S3),
+            (name, Some(_)) if BACKENDS.contains(&name) => {
+                bail!("the backend does not take an argument: {name:?}")
+            }
+            (name, _) => bail!(
+                "unknown or disabled backend: {name:?} (available: {})",
+                BACKENDS.join(", "),
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> Infer<'a> for IpsisPersistentStorageBackend {
+    type GenesisArgs = IpsisPersistentStorageBackendArgs;
+    type GenesisResult = Self;
+
+    async fn try_infer() -> Result<Self> {
+        let args: String = infer("ipsis_backend").unwrap_or_else(|_| BACKENDS[0].into());
+        Self::genesis(args.parse()?).await
+    }
+
+    async fn genesis(
+        args: <Self as Infer<'a>>::GenesisArgs,
+    ) -> Result<<Self as Infer<'a>>::GenesisResult> {
+        match args {
+            #[cfg(feature = "ipfs")]
+            IpsisPersistentStorageBackendArgs::Ipfs => {
+                ::ipsis_api_persistent_ipfs::IpsisPersistentStorageImpl::try_infer()
+                    .await
+                    .map(Self::Ipfs)
+            }
+            #[cfg(feature = "local")]
+            IpsisPersistentStorageBackendArgs::Local(dirs) if !dirs.is_empty() => {
+                ::ipsis_api_persistent_local::IpsisPersistentStorageImpl::genesis(dirs)
+                    .await
+                    .map(Self::Local)
+            }
+            #[cfg(feature = "local")]
+            IpsisPersistentStorageBackendArgs::Local(_) => {
+                ::ipsis_api_persistent_local::IpsisPersistentStorageImpl::try_infer()
+                    .await
+                    .map(Self::Local)
+            }
+            #[cfg(feature = "memory")]
+            IpsisPersistentStorageBackendArgs::Memory => {
+                ::ipsis_api_persistent_memory::IpsisPersistentStorageImpl::try_infer()
+                    .await
+                    .map(Self::Memory)
+            }
+            #[cfg(feature = "s3")]
+            IpsisPersistentStorageBackendArgs::S3 => {
+                ::ipsis_api_persistent_s3::IpsisPersistentStorageImpl::try_infer()
+                    .await
+                    .map(Self::S3)
+            }
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($self:ident, $storage:ident => $expr:expr) => {
+        match $self {
+            #[cfg(feature = "ipfs")]
+            Self::Ipfs($storage) => $expr,
+            #[cfg(feature = "local")]
+            Self::Local($storage) => $expr,
+            #[cfg(feature = "memory")]
+            Self::Memory($storage) => $expr,
+            #[cfg(feature = "s3")]
+            Self::S3($storage) => $expr,
+        }
+    };
+}
+
+#[async_trait]
+impl IpsisPersistentStorage for IpsisPersistentStorageBackend {
+    fn protocol(&self) -> &'static str {
+        dispatch!(self, storage => storage.protocol())
+    }
+
+    fn use_hash_as_native(&self) -> bool {
+        dispatch!(self, storage => storage.use_hash_as_native())
+    }
+
+    fn use_account_as_namespace(&self) -> bool {
+        dispatch!(self, storage => storage.use_account_as_namespace())
+    }
+
+    async fn get_raw<W>(&self, account: &AccountRef, path: &Path, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        dispatch!(self, storage => storage.get_raw(account, path, writer).await)
+    }
+
+    async fn get_raw_range<W>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        dispatch!(self, storage => {
+            storage
+                .get_raw_range(account, path, offset, len, writer)
+                .await
+        })
+    }
+
+    async fn put_raw<R>(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        reader: &mut R,
+    ) -> Result<Result<(), Path>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        dispatch!(self, storage => storage.put_raw(account, path, reader).await)
+    }
+
+    async fn import_file(
+        &self,
+        account: &AccountRef,
+        path: &Path,
+        file: &::std::path::Path,
+    ) -> Result<bool> {
+        dispatch!(self, storage => storage.import_file(account, path, file).await)
+    }
+
+    async fn contains(&self, account: &AccountRef, path: &Path) -> Result<bool> {
+        dispatch!(self, storage => storage.contains(account, path).await)
+    }
+
+    async fn stat(&self, account: &AccountRef, path: &Path) -> Result<Stat> {
+        dispatch!(self, storage => storage.stat(account, path).await)
+    }
+
+    async fn delete(&self, account: &AccountRef, path: &Path) -> Result<()> {
+        dispatch!(self, storage => storage.delete(account, path).await)
+    }
+
+    async fn list(&self, account: &AccountRef, query: &ListQuery) -> Result<ListPage> {
+        dispatch!(self, storage => storage.list(account, query).await)
+    }
+
+    async fn namespaces(&self) -> Result<Vec<AccountRef>> {
+        dispatch!(self, storage => storage.namespaces().await)
+    }
+
+    async fn repair(&self, account: &AccountRef) -> Result<usize> {
+        dispatch!(self, storage => storage.repair(account).await)
+    }
+}
+
+/// The names of the enabled backends, with the default one first.
+const BACKENDS: &[&str] = &[
+    #[cfg(feature = "local")]
+    "local",
+    #[cfg(feature = "s3")]
+    "s3",
+    #[cfg(feature = "ipfs")]
+    "ipfs",
+    #[cfg(feature = "memory")]
+    "memory",
+];
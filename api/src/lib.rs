@@ -7,11 +7,40 @@ pub mod client {
         IpsisClientInner<::ipiis_api::client::IpiisClient, super::IpsisPersistentStorageImpl>;
 }
 
+pub mod backend;
 pub mod server;
 
-#[cfg(feature = "ipfs")]
-use ipsis_api_persistent_ipfs::IpsisPersistentStorageImpl;
-#[cfg(feature = "local")]
-use ipsis_api_persistent_local::IpsisPersistentStorageImpl;
-#[cfg(feature = "s3")]
-use ipsis_api_persistent_s3::IpsisPersistentStorageImpl;
+use self::backend::IpsisPersistentStorageBackend as IpsisPersistentStorageBase;
+
+// both make the objects redundant, so only one of them can wrap the backend
+#[cfg(all(feature = "erasure", feature = "replica"))]
+compile_error!("the features \"erasure\" and \"replica\" cannot be enabled together");
+
+#[cfg(not(any(feature = "erasure", feature = "replica")))]
+type IpsisPersistentStorageRedundant = IpsisPersistentStorageBase;
+#[cfg(feature = "erasure")]
+type IpsisPersistentStorageRedundant =
+    ::ipsis_api_persistent_erasure::IpsisPersistentStorageImpl<IpsisPersistentStorageBase>;
+#[cfg(all(feature = "replica", not(feature = "erasure")))]
+type IpsisPersistentStorageRedundant =
+    ::ipsis_api_persistent_replica::IpsisPersistentStorageImpl<IpsisPersistentStorageBase>;
+
+// the cache is placed below the encryption, so that the cached data are also encrypted
+#[cfg(not(feature = "cache"))]
+type IpsisPersistentStorageCached = IpsisPersistentStorageRedundant;
+#[cfg(feature = "cache")]
+type IpsisPersistentStorageCached =
+    ::ipsis_api_persistent_cache::IpsisPersistentStorageImpl<IpsisPersistentStorageRedundant>;
+
+// the data are compressed before being encrypted, as the encrypted data cannot be compressed
+#[cfg(not(feature = "encrypt"))]
+type IpsisPersistentStorageEncrypted = IpsisPersistentStorageCached;
+#[cfg(feature = "encrypt")]
+type IpsisPersistentStorageEncrypted =
+    ::ipsis_api_persistent_encrypt::IpsisPersistentStorageImpl<IpsisPersistentStorageCached>;
+
+#[cfg(not(feature = "compress"))]
+type IpsisPersistentStorageImpl = IpsisPersistentStorageEncrypted;
+#[cfg(feature = "compress")]
+type IpsisPersistentStorageImpl =
+    ::ipsis_api_persistent_compress::IpsisPersistentStorageImpl<IpsisPersistentStorageEncrypted>;
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use ipiis_api::{
     client::IpiisClient,
@@ -13,11 +13,16 @@ use ipis::{
         data::Data,
     },
     env::Infer,
-    path::Path,
+    log::{info, warn},
     stream::DynStream,
-    tokio::io::{AsyncRead, AsyncReadExt},
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncReadExt},
+        task::JoinHandle,
+    },
 };
-use ipsis_common::Ipsis;
+use ipsis_api_common::policy::{IpsisOperation, IpsisPolicy};
+use ipsis_common::{Ipsis, Lease, PathRange};
 
 type IpsisClientInner =
     ::ipsis_api_common::client::IpsisClientInner<IpiisServer, super::IpsisPersistentStorageImpl>;
@@ -26,6 +31,56 @@ pub struct IpsisServer {
     client: Arc<IpsisClientInner>,
 }
 
+impl IpsisServer {
+    async fn new(client: IpsisClientInner) -> Result<Self> {
+        // the server keeps the journals locked while running
+        client.open_journals().await?;
+
+        Ok(Self {
+            client: client.into(),
+        })
+    }
+
+    /// Replaces the authorization policy.
+    ///
+    /// The policy can be replaced only before the server is shared (e.g. before calling `run`).
+    pub fn set_policy<Policy>(&mut self, policy: Policy) -> Result<()>
+    where
+        Policy: IpsisPolicy + Send + Sync + 'static,
+    {
+        match Arc::get_mut(&mut self.client) {
+            Some(client) => {
+                client.set_policy(policy);
+                Ok(())
+            }
+            None => bail!("failed to replace the policy of the shared server"),
+        }
+    }
+
+    /// Spawns a background task which periodically collects the expired objects.
+    ///
+    /// Returns `None` if the collection is disabled.
+    pub fn spawn_garbage_collector(&self) -> Option<JoinHandle<()>> {
+        let interval = match self.client.config().gc_interval_secs {
+            0 => return None,
+            secs => Duration::from_secs(secs),
+        };
+
+        let client = self.client.clone();
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                match client.collect_garbage().await {
+                    Ok(0) => {}
+                    Ok(num_deleted) => info!("collected {num_deleted} expired objects"),
+                    Err(e) => warn!("failed to collect the expired objects: {e}"),
+                }
+            }
+        }))
+    }
+}
+
 impl ::core::ops::Deref for IpsisServer {
     type Target = IpsisClientInner;
 
@@ -40,17 +95,13 @@ impl<'a> Infer<'a> for IpsisServer {
     type GenesisResult = Self;
 
     async fn try_infer() -> Result<Self> {
-        Ok(Self {
-            client: IpsisClientInner::try_infer().await?.into(),
-        })
+        Self::new(IpsisClientInner::try_infer().await?).await
     }
 
     async fn genesis(
         args: <Self as Infer<'a>>::GenesisArgs,
     ) -> Result<<Self as Infer<'a>>::GenesisResult> {
-        Ok(Self {
-            client: IpsisClientInner::genesis(args).await?.into(),
-        })
+        Self::new(IpsisClientInner::genesis(args).await?).await
     }
 }
 
@@ -60,11 +111,21 @@ handle_external_call!(
     request: ::ipsis_common::io => {
         Protocol => handle_protocol,
         Get => handle_get,
+        GetRange => handle_get_range,
+        BeginPut => handle_begin_put,
+        CommitPut => handle_commit_put,
+        PutManifest => handle_put_manifest,
+        Renew => handle_renew,
         Contains => handle_contains,
+        Stat => handle_stat,
         Delete => handle_delete,
+        List => handle_list,
+        Revoke => handle_revoke,
+        Scrub => handle_scrub,
     },
     request_raw: ::ipsis_common::io => {
         Put => handle_put,
+        PutPart => handle_put_part,
     },
 );
 
@@ -99,10 +160,17 @@ impl IpsisServer {
         let sign_as_guarantee = req.__sign.into_owned().await?;
 
         // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
         let path = sign_as_guarantee.data;
+        let capabilities = req.capabilities.into_owned().await?;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Get)?;
 
         // handle data
-        let mut data = client.get_raw(&path).await?;
+        let mut data = client
+            .get_raw_shared_for(&guarantee, &path, &capabilities)
+            .await?;
 
         // validate the length
         let len = data.read_u64().await?;
@@ -125,6 +193,46 @@ impl IpsisServer {
         })
     }
 
+    async fn handle_get_range(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::GetRange<'static>,
+    ) -> Result<::ipsis_common::io::response::GetRange<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let range = sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Get)?;
+
+        // handle data
+        let mut data = client
+            .get_raw_range_for(&guarantee, &range.path, range.offset, range.len)
+            .await?;
+
+        // validate the length
+        let len = data.read_u64().await?;
+        if range.len != len {
+            bail!("failed to validate the length")
+        }
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::GetRange {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+            data: ::ipis::stream::DynStream::Stream {
+                len: range.len,
+                recv: Box::pin(data),
+            },
+        })
+    }
+
     async fn handle_put<R>(
         client: &IpsisClientInner,
         mut recv: R,
@@ -133,11 +241,16 @@ impl IpsisServer {
         R: AsyncRead + Send + Sync + Unpin + 'static,
     {
         // recv sign
-        let sign_as_guarantee: Data<GuaranteeSigned, Path> =
+        let sign_as_guarantee: Data<GuaranteeSigned, Lease> =
             DynStream::recv(&mut recv).await?.into_owned().await?;
 
         // unpack data
-        let path = sign_as_guarantee.data;
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let lease = &sign_as_guarantee.data;
+        let path = lease.path;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Put)?;
 
         // validate the length
         let len = recv.read_u64().await?;
@@ -146,7 +259,7 @@ impl IpsisServer {
         }
 
         // handle data
-        client.put_raw(&path, recv).await?;
+        client.put_raw_for(&guarantee, lease, recv).await?;
 
         // sign data
         let server: &IpiisServer = client.as_ref();
@@ -159,6 +272,160 @@ impl IpsisServer {
         })
     }
 
+    async fn handle_begin_put(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::BeginPut<'static>,
+    ) -> Result<::ipsis_common::io::response::BeginPut<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let path = sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Put)?;
+
+        // handle data
+        let offset = client.begin_put_for(&guarantee, &path).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::BeginPut {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+            offset: ::ipis::stream::DynStream::Owned(offset),
+        })
+    }
+
+    async fn handle_put_part<R>(
+        client: &IpsisClientInner,
+        mut recv: R,
+    ) -> Result<::ipsis_common::io::response::PutPart<'static>>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // recv sign
+        let sign_as_guarantee: Data<GuaranteeSigned, PathRange> =
+            DynStream::recv(&mut recv).await?.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let range = sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Put)?;
+
+        // validate the length
+        let len = recv.read_u64().await?;
+        if range.len != len {
+            bail!("failed to validate the length")
+        }
+
+        // handle data
+        client.put_part_for(&guarantee, &range, recv).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::PutPart {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+        })
+    }
+
+    async fn handle_commit_put(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::CommitPut<'static>,
+    ) -> Result<::ipsis_common::io::response::CommitPut<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let lease = &sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Put)?;
+
+        // handle data
+        client.commit_put_for(&guarantee, lease).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::CommitPut {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+        })
+    }
+
+    async fn handle_put_manifest(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::PutManifest<'static>,
+    ) -> Result<::ipsis_common::io::response::PutManifest<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let lease = &sign_as_guarantee.data;
+        let manifest = req.manifest.into_owned().await?;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Put)?;
+
+        // handle data
+        client
+            .put_manifest_for(&guarantee, lease, &manifest)
+            .await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::PutManifest {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+        })
+    }
+
+    async fn handle_renew(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::Renew<'static>,
+    ) -> Result<::ipsis_common::io::response::Renew<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let lease = &sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Put)?;
+
+        // handle data
+        client.renew_for(&guarantee, lease).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::Renew {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+        })
+    }
+
     async fn handle_contains(
         client: &IpsisClientInner,
         req: ::ipsis_common::io::request::Contains<'static>,
@@ -167,10 +434,14 @@ impl IpsisServer {
         let sign_as_guarantee = req.__sign.into_owned().await?;
 
         // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
         let path = sign_as_guarantee.data;
 
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Contains)?;
+
         // handle data
-        let contains = client.contains(&path).await?;
+        let contains = client.contains_for(&guarantee, &path).await?;
 
         // sign data
         let server: &IpiisServer = client.as_ref();
@@ -184,6 +455,35 @@ impl IpsisServer {
         })
     }
 
+    async fn handle_stat(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::Stat<'static>,
+    ) -> Result<::ipsis_common::io::response::Stat<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let path = sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Stat)?;
+
+        // handle data
+        let stat = client.stat_for(&guarantee, &path).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::Stat {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+            stat: ::ipis::stream::DynStream::Owned(stat),
+        })
+    }
+
     async fn handle_delete(
         client: &IpsisClientInner,
         req: ::ipsis_common::io::request::Delete<'static>,
@@ -192,10 +492,14 @@ impl IpsisServer {
         let sign_as_guarantee = req.__sign.into_owned().await?;
 
         // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
         let path = sign_as_guarantee.data;
 
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Delete)?;
+
         // handle data
-        client.delete(&path).await?;
+        client.delete_for(&guarantee, &path).await?;
 
         // sign data
         let server: &IpiisServer = client.as_ref();
@@ -207,4 +511,90 @@ impl IpsisServer {
             __sign: ::ipis::stream::DynStream::Owned(sign),
         })
     }
+
+    async fn handle_list(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::List<'static>,
+    ) -> Result<::ipsis_common::io::response::List<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let query = &sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::List)?;
+
+        // handle data
+        let page = client.list_for(&guarantee, query).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::List {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+            page: ::ipis::stream::DynStream::Owned(page),
+        })
+    }
+
+    async fn handle_revoke(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::Revoke<'static>,
+    ) -> Result<::ipsis_common::io::response::Revoke<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let revocation = &sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Revoke)?;
+
+        // handle data
+        client.revoke_for(&guarantee, revocation).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::Revoke {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+        })
+    }
+
+    async fn handle_scrub(
+        client: &IpsisClientInner,
+        req: ::ipsis_common::io::request::Scrub<'static>,
+    ) -> Result<::ipsis_common::io::response::Scrub<'static>> {
+        // unpack sign
+        let sign_as_guarantee = req.__sign.into_owned().await?;
+
+        // unpack data
+        let guarantee = sign_as_guarantee.metadata.guarantee.account;
+        let query = &sign_as_guarantee.data;
+
+        // authorize the request
+        client.authorize(&guarantee, IpsisOperation::Scrub)?;
+
+        // handle data
+        let report = client.scrub_storage(query).await?;
+
+        // sign data
+        let server: &IpiisServer = client.as_ref();
+        let sign = server.sign_as_guarantor(sign_as_guarantee)?;
+
+        // pack data
+        Ok(::ipsis_common::io::response::Scrub {
+            __lifetime: Default::default(),
+            __sign: ::ipis::stream::DynStream::Owned(sign),
+            report: ::ipis::stream::DynStream::Owned(report),
+        })
+    }
 }
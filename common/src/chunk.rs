@@ -0,0 +1,140 @@
+use ipis::{
+    core::{anyhow::Result, value::hash::Hasher},
+    path::Path,
+    tokio::io::{AsyncRead, AsyncReadExt},
+};
+
+use crate::Manifest;
+
+pub const CHUNK_SIZE_MIN: usize = 16 * 1024;
+pub const CHUNK_SIZE_AVG: usize = 64 * 1024;
+pub const CHUNK_SIZE_MAX: usize = 256 * 1024;
+
+/// Splits a stream into content-defined chunks, using a gear-based rolling hash.
+///
+/// As the boundaries depend only on the nearby bytes, inserting or removing data
+/// changes only the chunks around the edit, so that the others can be deduplicated.
+pub struct Chunker<R> {
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R> Chunker<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(CHUNK_SIZE_MAX),
+            eof: false,
+        }
+    }
+
+    /// Returns the next chunk, or `None` if the stream is exhausted.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        // fill the buffer
+        if !self.eof {
+            let capacity = (CHUNK_SIZE_MAX - self.buf.len()) as u64;
+            let len = (&mut self.reader)
+                .take(capacity)
+                .read_to_end(&mut self.buf)
+                .await? as u64;
+            self.eof = len < capacity;
+        }
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        // cut the chunk
+        let boundary = Boundary::default()
+            .find(&self.buf)
+            .unwrap_or(self.buf.len());
+        let remaining = self.buf.split_off(boundary);
+        Ok(Some(::core::mem::replace(&mut self.buf, remaining)))
+    }
+}
+
+/// A rolling state to find the boundaries of the chunks.
+#[derive(Copy, Clone, Debug, Default)]
+struct Boundary {
+    len: usize,
+    hash: u64,
+}
+
+impl Boundary {
+    const MASK: u64 =
+        ((CHUNK_SIZE_AVG - 1) as u64) << (u64::BITS - CHUNK_SIZE_AVG.trailing_zeros());
+
+    /// Returns the length of the data until the end of the current chunk, if found.
+    fn find(&mut self, data: &[u8]) -> Option<usize> {
+        for (index, byte) in data.iter().enumerate() {
+            self.len += 1;
+            if self.len > CHUNK_SIZE_MIN {
+                self.hash = (self.hash << 1).wrapping_add(GEAR[*byte as usize]);
+            }
+
+            if self.len >= CHUNK_SIZE_MAX
+                || self.len > CHUNK_SIZE_MIN && self.hash & Self::MASK == 0
+            {
+                *self = Self::default();
+                return Some(index + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Digests a stream into the manifest which [`Chunker`] would produce from it.
+#[derive(Default)]
+pub struct ManifestHasher {
+    boundary: Boundary,
+    hasher: Hasher,
+    chunks: Vec<Path>,
+}
+
+impl ManifestHasher {
+    pub fn update(&mut self, mut data: &[u8]) {
+        while let Some(len) = self.boundary.find(data) {
+            self.hasher.update(&data[..len]);
+            self.push_chunk();
+            data = &data[len..];
+        }
+        self.hasher.update(data);
+    }
+
+    pub fn finalize(mut self) -> Manifest {
+        if self.hasher.len() > 0 {
+            self.push_chunk();
+        }
+        Manifest {
+            chunks: self.chunks,
+        }
+    }
+
+    fn push_chunk(&mut self) {
+        let hasher = ::core::mem::take(&mut self.hasher);
+        let len = hasher.len() as u64;
+        self.chunks.push(Path {
+            value: hasher.finalize(),
+            len,
+        });
+    }
+}
+
+/// The random table of the gear hash, which is generated by `splitmix64`.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0u64;
+    let mut index = 0;
+    while index < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[index] = z ^ (z >> 31);
+        index += 1;
+    }
+    table
+};
@@ -1,4 +1,6 @@
-use std::io::Cursor;
+pub mod chunk;
+
+use std::io::{Cursor, SeekFrom};
 
 use bytecheck::CheckBytes;
 use ipiis_common::{define_io, external_call, Ipiis, ServerResult};
@@ -6,17 +8,24 @@ use ipis::{
     async_trait::async_trait,
     class::Class,
     core::{
-        account::{GuaranteeSigned, GuarantorSigned, Verifier},
-        anyhow::Result,
+        account::{AccountRef, GuaranteeSigned, GuarantorSigned, Verifier},
+        anyhow::{bail, Result},
+        chrono::{Duration, Utc},
         data::Data,
         signature::SignatureSerializer,
         signed::{IsSigned, Serializer},
-        value::hash::Hash,
+        value::{
+            chrono::DateTime,
+            hash::{Hash, Hasher},
+        },
     },
     futures::TryFutureExt,
     path::Path,
     stream::DynStream,
-    tokio::io::AsyncRead,
+    tokio::{
+        fs::File,
+        io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt},
+    },
 };
 use rkyv::{
     de::deserializers::SharedDeserializeMap, validation::validators::DefaultValidator, Archive,
@@ -53,7 +62,31 @@ pub trait Ipsis {
             .await
     }
 
-    async fn get_raw(&self, path: &Path) -> Result<<Self as Ipsis>::Reader>;
+    async fn get_raw(&self, path: &Path) -> Result<<Self as Ipsis>::Reader> {
+        self.get_raw_shared(path, &[]).await
+    }
+
+    /// Reads an object which is owned by another account, by attaching a chain of grants.
+    ///
+    /// The chain should begin with a grant signed by the owner,
+    /// and each following grant should be signed by the grantee of the previous one.
+    async fn get_raw_shared(
+        &self,
+        path: &Path,
+        capabilities: &[SignedCapability],
+    ) -> Result<<Self as Ipsis>::Reader>;
+
+    /// Reads a range of an object.
+    ///
+    /// A range alone cannot be verified against the path of the whole object,
+    /// so only the chunks which cover the range are verified if the object is chunked;
+    /// the ranges of the other objects are served without verification.
+    async fn get_raw_range(
+        &self,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<<Self as Ipsis>::Reader>;
 
     async fn put<Req>(&self, data: &Req) -> Result<Path>
     where
@@ -70,12 +103,172 @@ pub trait Ipsis {
     }
 
     async fn put_raw<R>(&self, path: &Path, data: R) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        self.put_raw_with_lease(path, data, None).await
+    }
+
+    /// Stores an object which is held until the expiration date.
+    ///
+    /// If the expiration date is `None`, the object is held until it is deleted.
+    async fn put_raw_with_lease<R>(
+        &self,
+        path: &Path,
+        data: R,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static;
+
+    /// Stores a local file as an object, and returns its path.
+    ///
+    /// The file is hashed first, and then sent as the data by default.
+    /// The local storages may import the file without copying (e.g. by a reflink or a hardlink).
+    async fn import_raw(
+        &self,
+        local_path: &::std::path::Path,
+        expiration_date: Option<DateTime>,
+    ) -> Result<Path> {
+        // hash the file
+        let path = hash_file(local_path).await?;
+
+        // send the file
+        let file = File::open(local_path).await?;
+        self.put_raw_with_lease(&path, file, expiration_date)
+            .await?;
+        Ok(path)
+    }
+
+    /// Stores an object through an upload session.
+    ///
+    /// If the upload is interrupted, calling it again resumes from the committed offset.
+    async fn put_raw_resumable<R>(
+        &self,
+        path: &Path,
+        mut data: R,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()>
+    where
+        R: AsyncRead + AsyncSeek + Send + Sync + Unpin + 'static,
+    {
+        // resume the session
+        let offset = self.begin_put(path).await?;
+
+        // send the remaining data
+        if offset < path.len {
+            data.seek(SeekFrom::Start(offset)).await?;
+            self.put_part(path, offset, path.len - offset, data).await?;
+        }
+
+        self.commit_put(path, expiration_date).await
+    }
+
+    /// Opens an upload session, and returns the committed offset.
+    ///
+    /// If the session already exists, it is resumed.
+    async fn begin_put(&self, path: &Path) -> Result<u64>;
+
+    /// Sends a part of the upload.
+    ///
+    /// The offset should not exceed the committed offset.
+    async fn put_part<R>(&self, path: &Path, offset: u64, len: u64, data: R) -> Result<()>
     where
         R: AsyncRead + Send + Sync + Unpin + 'static;
 
+    /// Stores the completed upload, and closes the session.
+    async fn commit_put(&self, path: &Path, expiration_date: Option<DateTime>) -> Result<()>;
+
+    /// Stores a large object as content-defined chunks, and returns the path of its manifest.
+    ///
+    /// The chunks which have been already stored are skipped,
+    /// so that an interrupted upload can be resumed by calling it again.
+    async fn put_raw_chunked<R>(&self, data: R, expiration_date: Option<DateTime>) -> Result<Path>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // the chunks are held by the manifest after it is stored
+        let chunk_expiration_date: DateTime = (Utc::now() + Duration::days(1)).into();
+
+        let mut chunker = self::chunk::Chunker::new(data);
+        let mut chunks = vec![];
+        while let Some(chunk) = chunker.next_chunk().await? {
+            let path = Path {
+                value: Hash::with_bytes(&chunk),
+                len: chunk.len().try_into()?,
+            };
+
+            if !self.contains(&path).await? {
+                self.put_raw_with_lease(
+                    &path,
+                    Cursor::new(chunk),
+                    Some(chunk_expiration_date.clone()),
+                )
+                .await?;
+            }
+            chunks.push(path);
+        }
+
+        self.put_manifest(&Manifest { chunks }, expiration_date)
+            .await
+    }
+
+    /// Stores a manifest of the chunks which have been already stored.
+    ///
+    /// Reading the returned path yields the concatenated chunks.
+    async fn put_manifest(
+        &self,
+        manifest: &Manifest,
+        expiration_date: Option<DateTime>,
+    ) -> Result<Path>;
+
+    /// Replaces the expiration date of an object which has been stored by this account.
+    async fn renew(&self, path: &Path, expiration_date: Option<DateTime>) -> Result<()>;
+
     async fn contains(&self, path: &Path) -> Result<bool>;
 
+    /// Returns the metadata of the object.
+    ///
+    /// Only the hash of the given path is used, so the length may be unknown (e.g. `0`).
+    async fn stat(&self, path: &Path) -> Result<Stat>;
+
     async fn delete(&self, path: &Path) -> Result<()>;
+
+    async fn list(&self, query: &ListQuery) -> Result<ListPage>;
+
+    /// Signs a grant which allows the grantee to read the paths.
+    async fn grant(
+        &self,
+        grantee: &AccountRef,
+        paths: Vec<Path>,
+        expiration_date: Option<DateTime>,
+    ) -> Result<SignedCapability>;
+
+    /// Revokes a grant which has been signed by this account.
+    async fn revoke(&self, capability: &Capability) -> Result<()>;
+
+    /// Re-verifies the stored objects, and reports the corrupted or orphaned ones.
+    ///
+    /// It is an administrative operation, so it may be denied by the policy.
+    async fn scrub(&self, query: &ScrubQuery) -> Result<ScrubReport>;
+}
+
+/// Reads the local file, and returns the path which is derived from its data.
+pub async fn hash_file(local_path: &::std::path::Path) -> Result<Path> {
+    let mut file = File::open(local_path).await?;
+
+    let mut chunk = vec![0; 1 << 20];
+    let mut hasher = Hasher::default();
+    loop {
+        let len = file.read(&mut chunk).await?;
+        if len == 0 {
+            break Ok(Path {
+                len: hasher.len() as u64,
+                value: hasher.finalize(),
+            });
+        }
+        hasher.update(&chunk[..len]);
+    }
 }
 
 #[async_trait]
@@ -103,7 +296,11 @@ where
         Ok(protocol)
     }
 
-    async fn get_raw(&self, path: &Path) -> Result<<Self as Ipsis>::Reader> {
+    async fn get_raw_shared(
+        &self,
+        path: &Path,
+        capabilities: &[SignedCapability],
+    ) -> Result<<Self as Ipsis>::Reader> {
         // next target
         let target = self.get_account_primary(KIND.as_ref()).await?;
 
@@ -113,7 +310,9 @@ where
             target: KIND.as_ref() => &target,
             request: crate::io => Get,
             sign: self.sign_owned(target, *path)?,
-            inputs: { },
+            inputs: {
+                capabilities: capabilities.to_vec(),
+            },
             outputs: send,
         );
 
@@ -127,19 +326,67 @@ where
         Ok(recv)
     }
 
-    async fn put_raw<R>(&self, path: &Path, data: R) -> Result<()>
+    async fn get_raw_range(
+        &self,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<<Self as Ipsis>::Reader> {
+        // pack range
+        let range = PathRange {
+            path: *path,
+            offset,
+            len,
+        };
+        range.validate()?;
+
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // external call
+        let mut recv = external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => GetRange,
+            sign: self.sign_owned(target, range)?,
+            inputs: { },
+            outputs: send,
+        );
+
+        // recv sign
+        let sign: Data<GuarantorSigned, PathRange> =
+            DynStream::recv(&mut recv).await?.into_owned().await?;
+
+        // verify sign
+        let _ = sign.verify(Some(&target))?;
+
+        Ok(recv)
+    }
+
+    async fn put_raw_with_lease<R>(
+        &self,
+        path: &Path,
+        data: R,
+        expiration_date: Option<DateTime>,
+    ) -> Result<()>
     where
         R: AsyncRead + Send + Sync + Unpin + 'static,
     {
         // next target
         let target = self.get_account_primary(KIND.as_ref()).await?;
 
+        // pack data
+        let lease = Lease {
+            path: *path,
+            expiration_date,
+        };
+
         // external call
         external_call!(
             client: self,
             target: KIND.as_ref() => &target,
             request: crate::io => Put,
-            sign: self.sign_owned(target, *path)?,
+            sign: self.sign_owned(target, lease)?,
             inputs: {
                 data: DynStream::Stream {
                     len: path.len,
@@ -153,6 +400,137 @@ where
         Ok(())
     }
 
+    async fn begin_put(&self, path: &Path) -> Result<u64> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // external call
+        let (offset,) = external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => BeginPut,
+            sign: self.sign_owned(target, *path)?,
+            inputs: { },
+            outputs: { offset, },
+        );
+
+        // unpack response
+        Ok(offset)
+    }
+
+    async fn put_part<R>(&self, path: &Path, offset: u64, len: u64, data: R) -> Result<()>
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        // pack range
+        let range = PathRange {
+            path: *path,
+            offset,
+            len,
+        };
+        range.validate()?;
+
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // external call
+        external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => PutPart,
+            sign: self.sign_owned(target, range)?,
+            inputs: {
+                data: DynStream::Stream {
+                    len,
+                    recv: Box::pin(data),
+                },
+            },
+            inputs_mode: none,
+            outputs: { },
+        );
+
+        Ok(())
+    }
+
+    async fn commit_put(&self, path: &Path, expiration_date: Option<DateTime>) -> Result<()> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // pack data
+        let lease = Lease {
+            path: *path,
+            expiration_date,
+        };
+
+        // external call
+        external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => CommitPut,
+            sign: self.sign_owned(target, lease)?,
+            inputs: { },
+            outputs: { },
+        );
+
+        // unpack response
+        Ok(())
+    }
+
+    async fn put_manifest(
+        &self,
+        manifest: &Manifest,
+        expiration_date: Option<DateTime>,
+    ) -> Result<Path> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // pack data
+        let lease = Lease {
+            path: manifest.to_path()?,
+            expiration_date,
+        };
+        let path = lease.path;
+
+        // external call
+        external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => PutManifest,
+            sign: self.sign_owned(target, lease)?,
+            inputs: {
+                manifest: manifest.clone(),
+            },
+            outputs: { },
+        );
+
+        // unpack response
+        Ok(path)
+    }
+
+    async fn renew(&self, path: &Path, expiration_date: Option<DateTime>) -> Result<()> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // pack data
+        let lease = Lease {
+            path: *path,
+            expiration_date,
+        };
+
+        // external call
+        external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => Renew,
+            sign: self.sign_owned(target, lease)?,
+            inputs: { },
+            outputs: { },
+        );
+
+        // unpack response
+        Ok(())
+    }
+
     async fn contains(&self, path: &Path) -> Result<bool> {
         // next target
         let target = self.get_account_primary(KIND.as_ref()).await?;
@@ -171,6 +549,24 @@ where
         Ok(contains)
     }
 
+    async fn stat(&self, path: &Path) -> Result<Stat> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // external call
+        let (stat,) = external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => Stat,
+            sign: self.sign_owned(target, *path)?,
+            inputs: { },
+            outputs: { stat, },
+        );
+
+        // unpack response
+        Ok(stat)
+    }
+
     async fn delete(&self, path: &Path) -> Result<()> {
         // next target
         let target = self.get_account_primary(KIND.as_ref()).await?;
@@ -188,8 +584,286 @@ where
         // unpack response
         Ok(())
     }
+
+    async fn list(&self, query: &ListQuery) -> Result<ListPage> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // external call
+        let (page,) = external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => List,
+            sign: self.sign_owned(target, query.clone())?,
+            inputs: { },
+            outputs: { page, },
+        );
+
+        // unpack response
+        Ok(page)
+    }
+
+    async fn grant(
+        &self,
+        grantee: &AccountRef,
+        paths: Vec<Path>,
+        expiration_date: Option<DateTime>,
+    ) -> Result<SignedCapability> {
+        let capability = Capability {
+            grantee: *grantee,
+            paths,
+            expiration_date,
+        };
+
+        self.sign_owned(*grantee, capability)
+    }
+
+    async fn revoke(&self, capability: &Capability) -> Result<()> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // pack data
+        let revocation = Revocation {
+            capability: capability.to_hash()?,
+        };
+
+        // external call
+        external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => Revoke,
+            sign: self.sign_owned(target, revocation)?,
+            inputs: { },
+            outputs: { },
+        );
+
+        // unpack response
+        Ok(())
+    }
+
+    async fn scrub(&self, query: &ScrubQuery) -> Result<ScrubReport> {
+        // next target
+        let target = self.get_account_primary(KIND.as_ref()).await?;
+
+        // external call
+        let (report,) = external_call!(
+            client: self,
+            target: KIND.as_ref() => &target,
+            request: crate::io => Scrub,
+            sign: self.sign_owned(target, query.clone())?,
+            inputs: { },
+            outputs: { report, },
+        );
+
+        // unpack response
+        Ok(report)
+    }
+}
+
+#[derive(Class, Copy, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Copy, Clone, Debug, PartialEq, Eq))]
+pub struct PathRange {
+    pub path: Path,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl IsSigned for PathRange {}
+
+impl PathRange {
+    pub fn validate(&self) -> Result<()> {
+        match self.offset.checked_add(self.len) {
+            Some(end) if end <= self.path.len => Ok(()),
+            _ => bail!(
+                "range out of bounds: {}+{} exceeds {}",
+                self.offset,
+                self.len,
+                self.path.len,
+            ),
+        }
+    }
+}
+
+/// A list of the chunks which compose a large object.
+#[derive(Class, Clone, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct Manifest {
+    pub chunks: Vec<Path>,
+}
+
+impl IsSigned for Manifest {}
+
+impl Manifest {
+    /// Returns the total length of the chunks.
+    pub fn content_len(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.len).sum()
+    }
+
+    /// Returns the path of the object.
+    ///
+    /// The hash is of the manifest itself, but the length is of the content.
+    pub fn to_path(&self) -> Result<Path> {
+        Ok(Path {
+            value: Hash::with_bytes(&self.to_bytes()?),
+            len: self.content_len(),
+        })
+    }
+}
+
+/// A request to hold an object until the expiration date.
+#[derive(Class, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct Lease {
+    pub path: Path,
+    /// The date when the object can be collected, or `None` to hold it until it is deleted.
+    pub expiration_date: Option<DateTime>,
+}
+
+impl IsSigned for Lease {}
+
+#[derive(Class, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct ListQuery {
+    /// The cursor returned by the previous page, or `None` for the first page.
+    pub cursor: Option<String>,
+    /// The maximum number of paths to return, capped by [`LIST_LIMIT_MAX`].
+    pub limit: u32,
+}
+
+impl IsSigned for ListQuery {}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        Self {
+            cursor: None,
+            limit: LIST_LIMIT_MAX,
+        }
+    }
+}
+
+impl ListQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.clamp(1, LIST_LIMIT_MAX) as usize
+    }
+}
+
+#[derive(Class, Clone, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct ListPage {
+    pub paths: Vec<Path>,
+    /// The cursor to fetch the next page, or `None` if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl IsSigned for ListPage {}
+
+pub const LIST_LIMIT_MAX: u32 = 1_000;
+
+#[derive(Class, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct Stat {
+    /// The path of the object, including its actual length.
+    pub path: Path,
+    pub created_date: Option<DateTime>,
+    pub accessed_date: Option<DateTime>,
+    /// The protocol of the persistent storage which holds the object.
+    pub protocol: String,
+    pub content_type: Option<String>,
+}
+
+impl IsSigned for Stat {}
+
+/// A grant which allows the grantee to read the paths of the grantor.
+///
+/// The grant is signed by the grantor, targeting the grantee.
+#[derive(Class, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct Capability {
+    pub grantee: AccountRef,
+    pub paths: Vec<Path>,
+    pub expiration_date: Option<DateTime>,
+}
+
+impl IsSigned for Capability {}
+
+impl Capability {
+    /// Returns the identity of the grant, which is used to revoke it.
+    pub fn to_hash(&self) -> Result<Hash> {
+        Ok(Hash::with_bytes(&self.to_bytes()?))
+    }
+}
+
+pub type SignedCapability = Data<GuaranteeSigned, Capability>;
+
+#[derive(Class, Copy, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Copy, Clone, Debug, PartialEq, Eq))]
+pub struct Revocation {
+    /// The hash of the revoked [`Capability`].
+    pub capability: Hash,
+}
+
+impl IsSigned for Revocation {}
+
+#[derive(Class, Clone, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct ScrubQuery {
+    /// Moves the corrupted objects out of the persistent storage.
+    pub quarantine: bool,
+    /// Moves the orphaned objects out of the persistent storage, which are only reported otherwise.
+    ///
+    /// The objects which have been stored before the ledger was introduced are orphaned as well.
+    pub quarantine_orphans: bool,
+    /// Resumes from the checkpoint of the previous scrub, instead of starting over.
+    pub resume: bool,
+    /// The maximum number of objects to check, or `None` to check all of them.
+    ///
+    /// If the limit is reached, a checkpoint is saved so that the next scrub can resume from it.
+    pub limit: Option<u64>,
+}
+
+impl IsSigned for ScrubQuery {}
+
+#[derive(Class, Clone, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct ScrubReport {
+    /// The number of the checked objects.
+    pub num_checked: u64,
+    /// The total length of the checked objects.
+    pub num_bytes: u64,
+    /// The objects whose data mismatch their paths, or cannot be read.
+    pub corrupted: Vec<ScrubEntry>,
+    /// The objects which are referred by nobody.
+    pub orphaned: Vec<ScrubEntry>,
+    /// Whether every object has been checked, or the limit has been reached.
+    pub completed: bool,
+}
+
+impl IsSigned for ScrubReport {}
+
+#[derive(Class, Clone, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(compare(PartialEq))]
+#[archive_attr(derive(CheckBytes, Debug, PartialEq, Eq))]
+pub struct ScrubEntry {
+    pub namespace: AccountRef,
+    /// The path which is derived from the storage key.
+    pub path: Path,
+    pub reason: String,
+    /// Whether the object has been moved out of the persistent storage.
+    pub quarantined: bool,
 }
 
+impl IsSigned for ScrubEntry {}
+
 define_io! {
     Protocol {
         inputs: { },
@@ -201,7 +875,9 @@ define_io! {
         generics: { },
     },
     Get {
-        inputs: { },
+        inputs: {
+            capabilities: Vec<SignedCapability>,
+        },
         input_sign: Data<GuaranteeSigned, Path>,
         outputs: {
             data: Vec<u8>,
@@ -209,15 +885,65 @@ define_io! {
         output_sign: Data<GuarantorSigned, Path>,
         generics: { },
     },
+    GetRange {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, PathRange>,
+        outputs: {
+            data: Vec<u8>,
+        },
+        output_sign: Data<GuarantorSigned, PathRange>,
+        generics: { },
+    },
     Put {
         inputs: {
             data: Vec<u8>,
         },
-        input_sign: Data<GuaranteeSigned, Path>,
+        input_sign: Data<GuaranteeSigned, Lease>,
         outputs: { },
+        output_sign: Data<GuarantorSigned, Lease>,
+        generics: { },
+    },
+    BeginPut {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, Path>,
+        outputs: {
+            offset: u64,
+        },
         output_sign: Data<GuarantorSigned, Path>,
         generics: { },
     },
+    PutPart {
+        inputs: {
+            data: Vec<u8>,
+        },
+        input_sign: Data<GuaranteeSigned, PathRange>,
+        outputs: { },
+        output_sign: Data<GuarantorSigned, PathRange>,
+        generics: { },
+    },
+    CommitPut {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, Lease>,
+        outputs: { },
+        output_sign: Data<GuarantorSigned, Lease>,
+        generics: { },
+    },
+    PutManifest {
+        inputs: {
+            manifest: Manifest,
+        },
+        input_sign: Data<GuaranteeSigned, Lease>,
+        outputs: { },
+        output_sign: Data<GuarantorSigned, Lease>,
+        generics: { },
+    },
+    Renew {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, Lease>,
+        outputs: { },
+        output_sign: Data<GuarantorSigned, Lease>,
+        generics: { },
+    },
     Contains {
         inputs: { },
         input_sign: Data<GuaranteeSigned, Path>,
@@ -227,6 +953,15 @@ define_io! {
         output_sign: Data<GuarantorSigned, Path>,
         generics: { },
     },
+    Stat {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, Path>,
+        outputs: {
+            stat: Stat,
+        },
+        output_sign: Data<GuarantorSigned, Path>,
+        generics: { },
+    },
     Delete {
         inputs: { },
         input_sign: Data<GuaranteeSigned, Path>,
@@ -234,6 +969,31 @@ define_io! {
         output_sign: Data<GuarantorSigned, Path>,
         generics: { },
     },
+    List {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, ListQuery>,
+        outputs: {
+            page: ListPage,
+        },
+        output_sign: Data<GuarantorSigned, ListQuery>,
+        generics: { },
+    },
+    Revoke {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, Revocation>,
+        outputs: { },
+        output_sign: Data<GuarantorSigned, Revocation>,
+        generics: { },
+    },
+    Scrub {
+        inputs: { },
+        input_sign: Data<GuaranteeSigned, ScrubQuery>,
+        outputs: {
+            report: ScrubReport,
+        },
+        output_sign: Data<GuarantorSigned, ScrubQuery>,
+        generics: { },
+    },
 }
 
 ::ipis::lazy_static::lazy_static! {
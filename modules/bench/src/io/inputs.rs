@@ -69,6 +69,8 @@ pub enum ArgsProtocol {
     Ipfs,
     #[cfg(feature = "local")]
     Local,
+    #[cfg(feature = "memory")]
+    Memory,
     #[cfg(feature = "s3")]
     S3,
 }
@@ -14,6 +14,8 @@ mod ipfs;
 mod ipiis;
 #[cfg(feature = "local")]
 mod local;
+#[cfg(feature = "memory")]
+mod memory;
 #[cfg(feature = "s3")]
 mod s3;
 
@@ -42,6 +44,10 @@ pub async fn select(args: &crate::io::Args) -> Result<Box<dyn Protocol>> {
         crate::io::ArgsProtocol::Local => self::local::ProtocolImpl::try_new()
             .await
             .map(|protocol| Box::new(protocol) as Box<dyn Protocol>),
+        #[cfg(feature = "memory")]
+        crate::io::ArgsProtocol::Memory => self::memory::ProtocolImpl::try_new()
+            .await
+            .map(|protocol| Box::new(protocol) as Box<dyn Protocol>),
         #[cfg(feature = "s3")]
         crate::io::ArgsProtocol::S3 => self::s3::ProtocolImpl::try_new()
             .await
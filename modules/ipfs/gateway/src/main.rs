@@ -1,9 +1,11 @@
+mod range;
+
 use std::net::SocketAddr;
 
 use actix_web::{
     get,
     http::header::{self, CacheDirective, ETag, EntityTag},
-    web, App, HttpResponse, HttpServer, Responder,
+    web, App, HttpRequest, HttpResponse, HttpServer, Responder,
 };
 use actix_web_lab::body;
 use ipis::{
@@ -11,49 +13,168 @@ use ipis::{
     env::{infer, Infer},
     logger,
     path::Path,
-    tokio::{self, io::AsyncReadExt},
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+    },
 };
 use ipsis_api::{client::IpsisClient, common::Ipsis};
 
-#[get("/ipfs/{path}/{size}")]
+use crate::range::Ranges;
+
+#[get("/ipfs/{path}")]
 async fn get_ipfs(
+    req: HttpRequest,
+    client: web::Data<IpsisClient>,
+    path: web::Path<String>,
+) -> impl Responder {
+    // parse route
+    let hash_raw = path.into_inner();
+
+    get_ipfs_with_len(req, &client, hash_raw, None).await
+}
+
+#[get("/ipfs/{path}/{size}")]
+async fn get_ipfs_sized(
+    req: HttpRequest,
     client: web::Data<IpsisClient>,
     path: web::Path<(String, u64)>,
 ) -> impl Responder {
     // parse route
     let (hash_raw, len) = path.into_inner();
 
+    get_ipfs_with_len(req, &client, hash_raw, Some(len)).await
+}
+
+async fn get_ipfs_with_len(
+    req: HttpRequest,
+    client: &IpsisClient,
+    hash_raw: String,
+    len: Option<u64>,
+) -> HttpResponse {
     // parse path as IPFS-CID/IPI-HASH
     let hash: Hash = match hash_raw.parse() {
         Ok(hash) => hash,
         Err(e) => return HttpResponse::BadRequest().body(format!("{e}: {:?}", hash_raw.as_str())),
     };
-    let path = Path { value: hash, len };
+
+    // resolve the length
+    let (path, content_type) = match len {
+        Some(len) => (Path { value: hash, len }, None),
+        None => match client
+            .stat(&Path {
+                value: hash,
+                len: 0,
+            })
+            .await
+        {
+            Ok(stat) => (stat.path, stat.content_type),
+            Err(e) => {
+                return HttpResponse::NotFound().body(format!("{e}: {:?}", hash_raw.as_str()))
+            }
+        },
+    };
+    let len = path.len;
+
+    // parse the requested ranges
+    let etag = EntityTag::new_strong(hash_raw.to_owned());
+    let ranges = match Ranges::parse(&req, &etag, len) {
+        Ranges::Full => None,
+        Ranges::Partial(ranges) => Some(ranges),
+        Ranges::Unsatisfiable => {
+            return HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{len}")))
+                .finish()
+        }
+    };
 
     // start downloading the data
-    let mut data = match client.get_raw(&path).await {
-        Ok(data) => data,
-        Err(e) => {
-            return HttpResponse::NotFound()
-                .body(format!("{e}: {:?} as sized {len}", hash_raw.as_str()))
+    let mut parts = vec![];
+    for &(start, end) in ranges.iter().flatten() {
+        match client.get_raw_range(&path, start, end - start + 1).await {
+            Ok(data) => parts.push((start, end, data)),
+            Err(e) => {
+                return HttpResponse::NotFound()
+                    .body(format!("{e}: {:?} as sized {len}", hash_raw.as_str()))
+            }
         }
+    }
+    let data = match ranges {
+        Some(_) => None,
+        None => match client.get_raw(&path).await {
+            Ok(data) => Some(data),
+            Err(e) => {
+                return HttpResponse::NotFound()
+                    .body(format!("{e}: {:?} as sized {len}", hash_raw.as_str()))
+            }
+        },
     };
 
-    // drop the size header
-    match data.read_u64().await {
-        Ok(_) => {}
-        Err(_) => {
-            return HttpResponse::InternalServerError()
-                .body("Failed to connect to the IPSIS internal storage")
+    // drop the size headers
+    for data in parts
+        .iter_mut()
+        .map(|(_, _, data)| data)
+        .chain(data.as_mut())
+    {
+        match data.read_u64().await {
+            Ok(_) => {}
+            Err(_) => {
+                return HttpResponse::InternalServerError()
+                    .body("Failed to connect to the IPSIS internal storage")
+            }
         }
     }
 
-    // convert the datainto stream
+    // convert the data into stream
     let (mut tx, rx) = body::writer();
-    tokio::spawn(async move { tokio::io::copy(&mut data, &mut tx).await });
+    let mut res = match data {
+        Some(mut data) => {
+            tokio::spawn(async move { tokio::io::copy(&mut data, &mut tx).await });
+
+            let mut res = HttpResponse::Ok();
+            if let Some(content_type) = content_type {
+                res.insert_header((header::CONTENT_TYPE, content_type));
+            }
+            res
+        }
+        None if parts.len() == 1 => {
+            let (start, end, mut data) = parts.pop().unwrap();
+            tokio::spawn(async move { tokio::io::copy(&mut data, &mut tx).await });
+
+            let mut res = HttpResponse::PartialContent();
+            res.insert_header((header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")));
+            if let Some(content_type) = content_type {
+                res.insert_header((header::CONTENT_TYPE, content_type));
+            }
+            res
+        }
+        None => {
+            let boundary =
+                Hash::with_str(&format!("{hash_raw}/{}", DateTime::now().to_rfc3339())).to_string();
+            let content_type_multipart = format!("multipart/byteranges; boundary={boundary}");
+            let content_type = content_type.unwrap_or_else(|| "application/octet-stream".into());
+
+            tokio::spawn(async move {
+                for (start, end, mut data) in parts {
+                    let header = format!(
+                        "\r\n--{boundary}\r\n\
+                        Content-Type: {content_type}\r\n\
+                        Content-Range: bytes {start}-{end}/{len}\r\n\r\n",
+                    );
+                    tx.write_all(header.as_bytes()).await?;
+                    tokio::io::copy(&mut data, &mut tx).await?;
+                }
+                tx.write_all(format!("\r\n--{boundary}--\r\n").as_bytes())
+                    .await
+            });
+
+            let mut res = HttpResponse::PartialContent();
+            res.insert_header((header::CONTENT_TYPE, content_type_multipart));
+            res
+        }
+    };
 
-    HttpResponse::Ok()
-        .insert_header((header::ACCEPT_RANGES, "bytes"))
+    res.insert_header((header::ACCEPT_RANGES, "bytes"))
         .append_header((
             header::ACCESS_CONTROL_ALLOW_HEADERS,
             header::CONTENT_TYPE.as_str(),
@@ -83,7 +204,7 @@ async fn get_ipfs(
             CacheDirective::MaxAge(29_030_400),
             CacheDirective::Extension("immutable".to_owned(), None),
         ]))
-        .insert_header(ETag(EntityTag::new_strong(hash_raw.to_owned())))
+        .insert_header(ETag(etag))
         .insert_header(("X-Ipfs-Path", format!("/ipfs/{}", hash_raw.as_str())))
         .insert_header(("X-Ipfs-Roots", hash_raw.as_str()))
         .insert_header((header::DATE, DateTime::now().to_rfc2822()))
@@ -114,6 +235,7 @@ async fn main() {
             App::new()
                 .app_data(web::Data::clone(&client))
                 .service(get_ipfs)
+                .service(get_ipfs_sized)
                 .service(get_protocol)
         })
         .bind(addr)
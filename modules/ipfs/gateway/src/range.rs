@@ -0,0 +1,90 @@
+use actix_web::{
+    http::header::{EntityTag, Header, IfRange, Range, IF_RANGE},
+    HttpRequest,
+};
+
+/// Requests with more ranges than this are served as a whole.
+const MAX_RANGES: usize = 16;
+
+pub enum Ranges {
+    /// The whole content should be sent with `200 OK`.
+    Full,
+    /// The ranges (inclusive) should be sent with `206 Partial Content`.
+    Partial(Vec<(u64, u64)>),
+    /// None of the ranges can be satisfied (`416 Range Not Satisfiable`).
+    Unsatisfiable,
+}
+
+impl Ranges {
+    pub fn parse(req: &HttpRequest, etag: &EntityTag, len: u64) -> Self {
+        // parse the `Range` header
+        let specs = match Range::parse(req) {
+            Ok(Range::Bytes(specs)) => specs,
+            Ok(Range::Unregistered(..)) | Err(_) => return Self::Full,
+        };
+
+        // validate the `If-Range` header, if given
+        if req.headers().contains_key(IF_RANGE) {
+            match IfRange::parse(req) {
+                Ok(IfRange::EntityTag(tag)) if tag.strong_eq(etag) => {}
+                // the content has no last-modified date, so dates cannot be validated
+                Ok(IfRange::EntityTag(_) | IfRange::Date(_)) | Err(_) => return Self::Full,
+            }
+        }
+
+        if specs.len() > MAX_RANGES {
+            return Self::Full;
+        }
+
+        // resolve the ranges
+        let mut ranges: Vec<_> = specs
+            .iter()
+            .filter_map(|spec| spec.to_satisfiable_range(len))
+            .collect();
+        if ranges.is_empty() {
+            return Self::Unsatisfiable;
+        }
+
+        // coalesce overlapping ranges
+        ranges.sort_unstable();
+        let ranges =
+            ranges
+                .into_iter()
+                .fold(Vec::<(u64, u64)>::new(), |mut ranges, (start, end)| {
+                    match ranges.last_mut() {
+                        Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                        _ => ranges.push((start, end)),
+                    }
+                    ranges
+                });
+        Self::Partial(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::header::RANGE, test::TestRequest};
+
+    use super::*;
+
+    fn parse(if_range: Option<&str>) -> Ranges {
+        let mut req = TestRequest::default().insert_header((RANGE, "bytes=0-9"));
+        if let Some(if_range) = if_range {
+            req = req.insert_header((IF_RANGE, if_range));
+        }
+        Ranges::parse(
+            &req.to_http_request(),
+            &EntityTag::new_strong("tag".into()),
+            100,
+        )
+    }
+
+    #[test]
+    fn test_if_range() {
+        assert!(matches!(parse(None), Ranges::Partial(ranges) if ranges == [(0, 9)]));
+        assert!(matches!(parse(Some("\"tag\"")), Ranges::Partial(_)));
+        assert!(matches!(parse(Some("\"other\"")), Ranges::Full));
+        assert!(matches!(parse(Some("W/\"tag\"")), Ranges::Full));
+        assert!(matches!(parse(Some("malformed")), Ranges::Full));
+    }
+}
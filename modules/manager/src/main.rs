@@ -1,6 +1,48 @@
+use clap::{Parser, Subcommand};
 use ipiis_api::client::IpiisClient;
 use ipis::{async_trait::async_trait, core::anyhow::Result, env::Infer, path::Path, tokio};
-use ipsis_api::{client::IpsisClient, common::Ipsis};
+use ipsis_api::{
+    client::IpsisClient,
+    common::{Ipsis, ScrubEntry, ScrubQuery, ScrubReport},
+};
+
+#[derive(Debug, Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Re-verify the stored objects, and report the corrupted or orphaned ones
+    Scrub(ArgsScrub),
+    /// Restore the missing redundant copies of the stored objects (e.g. on the replicas)
+    Repair,
+}
+
+#[derive(Debug, Parser)]
+struct ArgsScrub {
+    /// Move the corrupted objects into the quarantine directory
+    #[clap(long)]
+    quarantine: bool,
+
+    /// Move the orphaned objects into the quarantine directory, which are only reported otherwise
+    #[clap(long)]
+    quarantine_orphans: bool,
+
+    /// Resume from the checkpoint of the previous scrub
+    #[clap(long)]
+    resume: bool,
+
+    /// Maximum number of objects to check
+    #[clap(long)]
+    limit: Option<u64>,
+
+    /// Scrub the primary server instead of the local storage
+    #[clap(long)]
+    remote: bool,
+}
 
 #[async_trait]
 trait IpsisExt {
@@ -40,10 +82,75 @@ where
     }
 }
 
+async fn scrub(client: &IpsisClient, args: ArgsScrub) -> Result<()> {
+    let query = ScrubQuery {
+        quarantine: args.quarantine,
+        quarantine_orphans: args.quarantine_orphans,
+        resume: args.resume,
+        limit: args.limit,
+    };
+
+    // scrub the storage
+    let report = if args.remote {
+        let primary: &IpiisClient = client.as_ref();
+        primary.scrub(&query).await?
+    } else {
+        client.scrub(&query).await?
+    };
+
+    print_report(&report);
+    Ok(())
+}
+
+async fn repair(client: &IpsisClient) -> Result<()> {
+    let num_restored = client.repair_storage().await?;
+    println!("restored {num_restored} copies");
+    Ok(())
+}
+
+fn print_report(report: &ScrubReport) {
+    fn print_entries(kind: &str, entries: &[ScrubEntry]) {
+        for entry in entries {
+            println!(
+                "{kind}\t{}\t{}\t{}{}",
+                entry.namespace.to_string(),
+                entry.path.value.to_string(),
+                entry.reason,
+                if entry.quarantined {
+                    " (quarantined)"
+                } else {
+                    ""
+                },
+            );
+        }
+    }
+
+    print_entries("corrupted", &report.corrupted);
+    print_entries("orphaned", &report.orphaned);
+    println!(
+        "checked {} objects ({} bytes): {} corrupted, {} orphaned{}",
+        report.num_checked,
+        report.num_bytes,
+        report.corrupted.len(),
+        report.orphaned.len(),
+        if report.completed {
+            ""
+        } else {
+            " (incomplete; resume with --resume)"
+        },
+    );
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Parse arguments
+    let args = Args::parse();
+
     // Initialize client
     let client = IpsisClient::try_infer().await?;
 
-    todo!()
+    match args.command {
+        Command::Scrub(args) => scrub(&client, args).await,
+        Command::Repair => repair(&client).await,
+    }
 }
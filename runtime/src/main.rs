@@ -3,5 +3,10 @@ use ipsis_api::server::IpsisServer;
 
 #[tokio::main]
 async fn main() {
-    IpsisServer::infer().await.run().await
+    let server = IpsisServer::infer().await;
+
+    // collect the expired objects in background
+    server.spawn_garbage_collector();
+
+    server.run().await
 }